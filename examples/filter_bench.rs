@@ -0,0 +1,55 @@
+use std::env;
+use std::time::Instant;
+use teeny_qoi::encoder::Encoder;
+use teeny_qoi::filter::{self, Filter};
+use teeny_qoi::{Header, RgbaPixel};
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() {
+        panic!("usage: filter_bench images...");
+    }
+
+    for path in &args {
+        let image = image::open(path).expect("couldn't read image");
+        let channels = if image.color().has_alpha() { 4 } else { 3 };
+        let image = image.into_rgba8();
+        let (width, height) = image.dimensions();
+        let pixels = RgbaPixel::slice_from_bytes(image.as_raw()).unwrap();
+        let header = Header {
+            channels,
+            ..Header::rgba(width, height)
+        };
+
+        println!("{} ({}x{}):", path, width, height);
+
+        let start = Instant::now();
+        let plain = Encoder::new(header).unwrap().pixels_to_vec(pixels).unwrap();
+        println!(
+            "  {:>8}: {:>10} bytes, {:>6.1} ms",
+            "none",
+            plain.len(),
+            start.elapsed().as_secs_f64() * 1000.0
+        );
+
+        for (name, f) in [("up", Filter::Up), ("ycocg-r", Filter::YCoCgR)] {
+            let start = Instant::now();
+            let filtered = filter::encode_filtered(header, pixels, f).unwrap();
+            let encode_time = start.elapsed().as_secs_f64() * 1000.0;
+
+            let start = Instant::now();
+            let (_, decoded, _) = filter::decode_filtered(&filtered).unwrap();
+            let decode_time = start.elapsed().as_secs_f64() * 1000.0;
+            assert!(decoded == pixels, "filter didn't round trip");
+
+            println!(
+                "  {:>8}: {:>10} bytes, {:>6.1} ms ({:+.1}% size, decoded in {:.1} ms)",
+                name,
+                filtered.len(),
+                encode_time,
+                (filtered.len() as f64 / plain.len() as f64 - 1.0) * 100.0,
+                decode_time
+            );
+        }
+    }
+}
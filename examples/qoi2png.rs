@@ -1,6 +1,7 @@
 use image::RgbaImage;
 use std::env;
-use teeny_qoi::decoder::SliceReader;
+use teeny_qoi::decoder;
+use zerocopy::AsBytes;
 
 fn main() {
     let args: Vec<String> = env::args().skip(1).collect();
@@ -9,12 +10,12 @@ fn main() {
     }
 
     let input = std::fs::read(&args[0]).expect("couldn't read input");
-    let (header, reader) = SliceReader::start(&input[..]).expect("invalid qoi file");
+    let (header, pixels) = decoder::decode_to_vec(&input[..]).expect("invalid qoi file");
     let image = RgbaImage::from_vec(
         header.width.get(),
         header.height.get(),
-        reader.into_decoder().into_rgba_bytes().collect::<Vec<u8>>(),
+        pixels.as_bytes().to_vec(),
     )
     .expect("couldn't create output image - wrong size?");
-    img.save(&args[1]).expect("couldn't write image");
+    image.save(&args[1]).expect("couldn't write image");
 }
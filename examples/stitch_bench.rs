@@ -0,0 +1,45 @@
+use std::env;
+use std::time::Instant;
+use teeny_qoi::encoder::Encoder;
+use teeny_qoi::stripes;
+use teeny_qoi::{Header, RgbaPixel};
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() {
+        panic!("usage: stitch_bench images...");
+    }
+
+    for path in &args {
+        let image = image::open(path).expect("couldn't read image");
+        let channels = if image.color().has_alpha() { 4 } else { 3 };
+        let image = image.into_rgba8();
+        let (width, height) = image.dimensions();
+        let pixels = RgbaPixel::slice_from_bytes(image.as_raw()).unwrap();
+        let header = Header {
+            channels,
+            ..Header::rgba(width, height)
+        };
+
+        println!("{} ({}x{}):", path, width, height);
+
+        let start = Instant::now();
+        let serial = Encoder::new(header).unwrap().pixels_to_vec(pixels).unwrap();
+        let serial_time = start.elapsed().as_secs_f64() * 1000.0;
+        println!("  {:>8}: {:>6.1} ms", "serial", serial_time);
+
+        for stripe_height in [16, 64, 256] {
+            let start = Instant::now();
+            let stitched = stripes::encode_stitched(header, pixels, stripe_height).unwrap();
+            let stitched_time = start.elapsed().as_secs_f64() * 1000.0;
+            assert!(stitched == serial, "stitched output differs from serial");
+
+            println!(
+                "  {:>8}: {:>6.1} ms ({:.2}x serial)",
+                format!("{} rows", stripe_height),
+                stitched_time,
+                serial_time / stitched_time
+            );
+        }
+    }
+}
@@ -0,0 +1,739 @@
+//! Animated QOI files, holding a sequence of frames drawn onto a canvas.
+//!
+//! An animated file is made of:
+//! - the `qoia` magic
+//! - an [AnimationHeader]
+//! - every frame, as a [FrameHeader] followed by a standard QOI file holding the frame's rectangle.
+//!
+//! A frame overwrites its rectangle of the canvas, which starts out transparent black. Once the frame has been shown for its delay,
+//! its rectangle is disposed of as its [Disposal] says. With delta frames, [AnimationEncoder] only encodes the rectangle that changed since the previous frame.
+
+use crate::decoder::{decode_to_vec, SliceReader};
+use crate::encoder::{check_pixel_count, EncodeError, Encoder};
+use crate::*;
+use zerocopy::LayoutVerified;
+
+/// Magic bytes for animated QOI files.
+pub const ANIMATED_MAGIC: [u8; 4] = [b'q', b'o', b'i', b'a'];
+
+/// The header of an animated QOI file.
+#[derive(AsBytes, FromBytes, Clone, Copy, Debug)]
+#[repr(C)]
+pub struct AnimationHeader {
+    /// The header of the canvas. Frames share its channels and colorspace.
+    pub header: Header,
+    pub frame_count: U32<BigEndian>,
+    /// How many times the animation plays, or 0 to loop forever.
+    pub loops: U32<BigEndian>,
+}
+
+/// The header of a frame, placing it on the canvas.
+#[derive(AsBytes, FromBytes, Clone, Copy, Debug)]
+#[repr(C)]
+pub struct FrameHeader {
+    pub x: U32<BigEndian>,
+    pub y: U32<BigEndian>,
+    /// How long the frame is shown, in milliseconds.
+    pub delay_ms: U32<BigEndian>,
+    /// The frame's [Disposal], as a byte.
+    pub disposal: u8,
+    /// The length of the frame's QOI file.
+    pub length: U32<BigEndian>,
+}
+
+/// What happens to a frame's rectangle once it has been shown.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Disposal {
+    /// The frame is left on the canvas.
+    Keep = 0,
+    /// The rectangle is cleared to transparent black.
+    Background = 1,
+    /// The rectangle is restored to what it was before the frame was drawn.
+    Previous = 2,
+}
+
+impl Disposal {
+    /// Reads a disposal from its byte, returning None if it's unknown.
+    pub fn from_u8(value: u8) -> Option<Disposal> {
+        match value {
+            0 => Some(Disposal::Keep),
+            1 => Some(Disposal::Background),
+            2 => Some(Disposal::Previous),
+            _ => None,
+        }
+    }
+}
+
+/// A frame's rectangle on the canvas, along with its timing and disposal.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FrameInfo {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub delay_ms: u32,
+    pub disposal: Disposal,
+}
+
+// copies rows of `width` pixels from `src` into `dst`, whose rows are `dst_width` pixels long, starting at (x, y)
+fn blit(
+    dst: &mut [RgbaPixel],
+    dst_width: usize,
+    src: &[RgbaPixel],
+    width: usize,
+    x: usize,
+    y: usize,
+) {
+    for (row, src_row) in src.chunks_exact(width).enumerate() {
+        let start = (y + row) * dst_width + x;
+        dst[start..start + width].copy_from_slice(src_row);
+    }
+}
+
+// copies the rectangle at (x, y) out of `src`, whose rows are `src_width` pixels long
+fn crop(src: &[RgbaPixel], src_width: usize, frame: &FrameInfo, out: &mut Vec<RgbaPixel>) {
+    out.clear();
+    for row in 0..frame.height as usize {
+        let start = (frame.y as usize + row) * src_width + frame.x as usize;
+        out.extend_from_slice(&src[start..start + frame.width as usize]);
+    }
+}
+
+// checks that a frame is non-empty & fits within a canvas of width * height pixels
+fn fits(width: u32, height: u32, frame: &FrameInfo) -> bool {
+    frame.width > 0
+        && frame.height > 0
+        && frame
+            .x
+            .checked_add(frame.width)
+            .is_some_and(|right| right <= width)
+        && frame
+            .y
+            .checked_add(frame.height)
+            .is_some_and(|bottom| bottom <= height)
+}
+
+/// The canvas of an animation, drawing frames and disposing of them.
+#[derive(Clone, Debug)]
+pub struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<RgbaPixel>,
+    // the last frame drawn, waiting to be disposed of, with the pixels it covered if it's restored afterwards
+    pending: Option<(FrameInfo, Vec<RgbaPixel>)>,
+}
+
+impl Canvas {
+    /// Builds a transparent black canvas the size of `header`.
+    ///
+    /// # Panics
+    /// Panics if the header is invalid or the canvas can't be allocated; use [Canvas::try_new] for untrusted headers.
+    pub fn new(header: &Header) -> Canvas {
+        Canvas::try_new(header).expect("invalid or too large canvas")
+    }
+
+    /// Builds a transparent black canvas the size of `header`, returning None if the header is invalid or the canvas can't be allocated.
+    pub fn try_new(header: &Header) -> Option<Canvas> {
+        if !header.is_valid() {
+            return None;
+        }
+
+        let count = header.pixel_count() as usize;
+        let mut pixels = Vec::new();
+        pixels.try_reserve_exact(count).ok()?;
+        pixels.resize(
+            count,
+            RgbaPixel {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: 0,
+            },
+        );
+
+        Some(Canvas {
+            width: header.width.get(),
+            height: header.height.get(),
+            pixels,
+            pending: None,
+        })
+    }
+
+    /// The pixels of the canvas, as shown with the last frame drawn.
+    pub fn pixels(&self) -> &[RgbaPixel] {
+        &self.pixels
+    }
+
+    /// Checks that a frame fits within the canvas.
+    pub fn contains(&self, frame: &FrameInfo) -> bool {
+        fits(self.width, self.height, frame)
+    }
+
+    /// Disposes of the previous frame, then draws `pixels` over the frame's rectangle. Returns None if the frame doesn't fit or `pixels` has the wrong length.
+    pub fn draw(&mut self, frame: &FrameInfo, pixels: &[RgbaPixel]) -> Option<&[RgbaPixel]> {
+        if !self.contains(frame) || pixels.len() != (frame.width * frame.height) as usize {
+            return None;
+        }
+
+        self.dispose();
+
+        let mut covered = Vec::new();
+        if frame.disposal == Disposal::Previous {
+            crop(&self.pixels, self.width as usize, frame, &mut covered);
+        }
+
+        blit(
+            &mut self.pixels,
+            self.width as usize,
+            pixels,
+            frame.width as usize,
+            frame.x as usize,
+            frame.y as usize,
+        );
+        self.pending = Some((*frame, covered));
+
+        Some(&self.pixels)
+    }
+
+    /// Disposes of the last frame drawn, leaving the canvas as the next frame will be drawn onto.
+    pub fn dispose(&mut self) {
+        let (frame, covered) = match self.pending.take() {
+            Some(pending) => pending,
+            None => return,
+        };
+
+        let (width, x, y) = (frame.width as usize, frame.x as usize, frame.y as usize);
+        match frame.disposal {
+            Disposal::Keep => (),
+            Disposal::Background => {
+                for row in 0..frame.height as usize {
+                    let start = (y + row) * self.width as usize + x;
+                    self.pixels[start..start + width].fill(RgbaPixel {
+                        r: 0,
+                        g: 0,
+                        b: 0,
+                        a: 0,
+                    });
+                }
+            }
+            Disposal::Previous => {
+                blit(&mut self.pixels, self.width as usize, &covered, width, x, y)
+            }
+        }
+    }
+}
+
+/// An encoder for animated QOI files, encoding every frame with an [Encoder].
+pub struct AnimationEncoder {
+    header: Header,
+    out: Vec<u8>,
+    frame_count: u32,
+    // the canvas as decoders will see it, kept to find the rectangles of delta frames
+    canvas: Option<Canvas>,
+    rect: Vec<RgbaPixel>,
+}
+
+impl AnimationEncoder {
+    /// Builds an encoder for an animation on a canvas described by `header`, failing if the header is invalid.
+    pub fn new(header: Header) -> Result<AnimationEncoder, EncodeError> {
+        if !header.is_valid() {
+            return Err(EncodeError::InvalidHeader);
+        }
+
+        let mut out = Vec::new();
+        out.extend_from_slice(&ANIMATED_MAGIC);
+        out.extend_from_slice(
+            AnimationHeader {
+                header,
+                frame_count: 0.into(),
+                loops: 0.into(),
+            }
+            .as_bytes(),
+        );
+
+        Ok(AnimationEncoder {
+            header,
+            out,
+            frame_count: 0,
+            canvas: None,
+            rect: Vec::new(),
+        })
+    }
+
+    /// Sets how many times the animation plays, 0 looping forever.
+    pub fn with_loops(mut self, loops: u32) -> AnimationEncoder {
+        let header = self.animation_header();
+        header.loops = loops.into();
+        self
+    }
+
+    /// Encodes frames pushed with [AnimationEncoder::push_frame] as the rectangle that changed since the previous frame.
+    pub fn with_delta_frames(mut self) -> AnimationEncoder {
+        self.canvas = Some(Canvas::new(&self.header));
+        self
+    }
+
+    fn animation_header(&mut self) -> &mut AnimationHeader {
+        LayoutVerified::<_, AnimationHeader>::new_from_prefix(&mut self.out[4..])
+            .unwrap()
+            .0
+            .into_mut()
+    }
+
+    /// Pushes a frame covering the whole canvas, kept on the canvas once shown.
+    ///
+    /// With delta frames, only the rectangle that changed since the previous frame is encoded.
+    /// Such a frame is smaller than the canvas, so disposing of it any other way would only clear or restore part of the image;
+    /// push frames with other disposals through [AnimationEncoder::push_region], giving their rectangle explicitly.
+    pub fn push_frame(&mut self, pixels: &[RgbaPixel], delay_ms: u32) -> Result<(), EncodeError> {
+        check_pixel_count(&self.header, pixels.len())?;
+
+        let mut frame = FrameInfo {
+            x: 0,
+            y: 0,
+            width: self.header.width.get(),
+            height: self.header.height.get(),
+            delay_ms,
+            disposal: Disposal::Keep,
+        };
+
+        let canvas = match &mut self.canvas {
+            Some(canvas) => canvas,
+            None => return self.push_region(&frame, pixels),
+        };
+
+        canvas.dispose();
+        let width = frame.width as usize;
+
+        // left, top, right & bottom of the changed pixels
+        let mut bounds: Option<(usize, usize, usize, usize)> = None;
+        let rows = pixels
+            .chunks_exact(width)
+            .zip(canvas.pixels().chunks_exact(width));
+        for (y, (row, shown)) in rows.enumerate() {
+            let mut changed = row
+                .iter()
+                .zip(shown)
+                .enumerate()
+                .filter(|(_, (a, b))| a != b)
+                .map(|(x, _)| x);
+
+            if let Some(left) = changed.next() {
+                let right = changed.next_back().unwrap_or(left);
+                bounds = Some(match bounds {
+                    Some((l, top, r, _)) => (l.min(left), top, r.max(right), y),
+                    None => (left, y, right, y),
+                });
+            }
+        }
+
+        // an unchanged frame still needs a rectangle, so it keeps the top left pixel
+        let (left, top, right, bottom) = bounds.unwrap_or((0, 0, 0, 0));
+        frame.x = left as u32;
+        frame.y = top as u32;
+        frame.width = (right - left + 1) as u32;
+        frame.height = (bottom - top + 1) as u32;
+
+        let mut rect = core::mem::take(&mut self.rect);
+        crop(pixels, width, &frame, &mut rect);
+        let result = self.push_region(&frame, &rect);
+        self.rect = rect;
+
+        result
+    }
+
+    /// Pushes a frame covering a rectangle of the canvas. Fails if the rectangle doesn't fit within the canvas.
+    pub fn push_region(
+        &mut self,
+        frame: &FrameInfo,
+        pixels: &[RgbaPixel],
+    ) -> Result<(), EncodeError> {
+        if !fits(self.header.width.get(), self.header.height.get(), frame) {
+            return Err(EncodeError::FrameOutOfBounds);
+        }
+
+        let header = Header {
+            width: frame.width.into(),
+            height: frame.height.into(),
+            ..self.header
+        };
+
+        let qoi = Encoder::new(header)?.pixels_to_vec(pixels)?;
+        if let Some(canvas) = &mut self.canvas {
+            canvas.draw(frame, pixels);
+        }
+
+        self.out.extend_from_slice(
+            FrameHeader {
+                x: frame.x.into(),
+                y: frame.y.into(),
+                delay_ms: frame.delay_ms.into(),
+                disposal: frame.disposal as u8,
+                length: (qoi.len() as u32).into(),
+            }
+            .as_bytes(),
+        );
+        self.out.extend_from_slice(&qoi);
+
+        self.frame_count += 1;
+        let frame_count = self.frame_count;
+        self.animation_header().frame_count = frame_count.into();
+
+        Ok(())
+    }
+
+    /// Returns the animated QOI file.
+    pub fn finish(self) -> Vec<u8> {
+        self.out
+    }
+}
+
+/// A frame of an animated QOI file.
+#[derive(Clone, Copy, Debug)]
+pub struct Frame<'a> {
+    pub header: FrameHeader,
+    /// The frame's QOI file.
+    pub data: &'a [u8],
+}
+
+impl<'a> Frame<'a> {
+    /// Starts reading the frame's QOI file, returning its header and a reader over its chunks.
+    pub fn reader(&self) -> Option<(Header, SliceReader<'a>)> {
+        SliceReader::start(self.data)
+    }
+
+    /// The frame's rectangle, timing and disposal. Returns None if the frame's QOI file or disposal is invalid.
+    pub fn info(&self) -> Option<FrameInfo> {
+        let (header, _) = self.reader()?;
+
+        Some(FrameInfo {
+            x: self.header.x.get(),
+            y: self.header.y.get(),
+            width: header.width.get(),
+            height: header.height.get(),
+            delay_ms: self.header.delay_ms.get(),
+            disposal: Disposal::from_u8(self.header.disposal)?,
+        })
+    }
+}
+
+/// A reader over the frames of an animated QOI file.
+pub struct AnimationReader<'a> {
+    inner: &'a [u8],
+    remaining: u32,
+}
+
+impl<'a> AnimationReader<'a> {
+    /// Initializes the reader, returning the animation's header and a reader if it's an animated QOI file.
+    pub fn start(data: &'a [u8]) -> Option<(AnimationHeader, AnimationReader<'a>)> {
+        if *data.get(0..4)? != ANIMATED_MAGIC {
+            return None;
+        }
+
+        let (header, inner) = LayoutVerified::<_, AnimationHeader>::new_from_prefix(&data[4..])?;
+
+        Some((
+            *header,
+            AnimationReader {
+                inner,
+                remaining: header.frame_count.get(),
+            },
+        ))
+    }
+}
+
+impl<'a> Iterator for AnimationReader<'a> {
+    type Item = Frame<'a>;
+
+    fn next(&mut self) -> Option<Frame<'a>> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let (header, rest) = LayoutVerified::<_, FrameHeader>::new_from_prefix(self.inner)?;
+        let length = header.length.get() as usize;
+        if length > rest.len() {
+            self.remaining = 0;
+            return None;
+        }
+
+        let (data, rest) = rest.split_at(length);
+        self.inner = rest;
+        self.remaining -= 1;
+
+        Some(Frame {
+            header: *header,
+            data,
+        })
+    }
+}
+
+/// A frame decoded by [decode_frames].
+#[derive(Clone, Debug)]
+pub struct DecodedFrame {
+    /// The canvas as shown with the frame.
+    pub pixels: Vec<RgbaPixel>,
+    pub delay_ms: u32,
+}
+
+/// Decodes every frame of an animated QOI file, returning its header and frames. Returns None if the file is invalid.
+pub fn decode_frames(data: &[u8]) -> Option<(AnimationHeader, Vec<DecodedFrame>)> {
+    let (header, reader) = AnimationReader::start(data)?;
+    if !header.header.is_valid() {
+        return None;
+    }
+
+    let (width, height) = (header.header.width.get(), header.header.height.get());
+    let mut canvas = None;
+    let mut frames = Vec::new();
+    for frame in reader {
+        // the frame's size comes from its own header, so check it before allocating for its pixels
+        let info = frame.info()?;
+        if !fits(width, height, &info) {
+            return None;
+        }
+
+        // the canvas's size is untrusted too, so it's only allocated once there's a frame to draw, and that may fail
+        if canvas.is_none() {
+            canvas = Some(Canvas::try_new(&header.header)?);
+        }
+
+        let (_, pixels) = decode_to_vec(frame.data)?;
+        frames.push(DecodedFrame {
+            pixels: canvas.as_mut()?.draw(&info, &pixels)?.to_vec(),
+            delay_ms: info.delay_ms,
+        });
+    }
+
+    (frames.len() == header.frame_count.get() as usize).then_some((header, frames))
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::test_utils::{noisy_image, random_pixel, rng};
+
+    const CLEAR: RgbaPixel = RgbaPixel {
+        r: 0,
+        g: 0,
+        b: 0,
+        a: 0,
+    };
+
+    // pastes a rectangle of `width` pixels per row at (x, y) of a canvas `canvas_width` pixels wide
+    fn paste(
+        canvas: &mut [RgbaPixel],
+        canvas_width: usize,
+        x: usize,
+        y: usize,
+        width: usize,
+        pixels: &[RgbaPixel],
+    ) {
+        for (row, pixels) in pixels.chunks(width).enumerate() {
+            let start = (y + row) * canvas_width + x;
+            canvas[start..start + width].copy_from_slice(pixels);
+        }
+    }
+
+    #[test]
+    fn decode_frames_rejects_frames_larger_than_the_canvas() {
+        let pixel = RgbaPixel {
+            r: 10,
+            g: 20,
+            b: 30,
+            a: 255,
+        };
+        let mut encoder = AnimationEncoder::new(Header::rgba(2, 2)).unwrap();
+        encoder.push_frame(&[pixel; 4], 100).unwrap();
+        let mut data = encoder.finish();
+
+        let (_, frames) = decode_frames(&data).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].pixels, [pixel; 4]);
+
+        // make the frame's own header claim 65535x65535 pixels
+        let frame_start = data
+            .windows(4)
+            .position(|magic| magic == tags::QOI_MAGIC)
+            .unwrap();
+        data[frame_start + 4..frame_start + 12]
+            .copy_from_slice(&[0, 0, 0xff, 0xff, 0, 0, 0xff, 0xff]);
+        assert!(decode_frames(&data).is_none());
+    }
+
+    #[test]
+    fn decode_frames_rejects_huge_canvases() {
+        // a 22 byte file claiming a 65535x65535 canvas
+        let mut data = ANIMATED_MAGIC.to_vec();
+        data.extend_from_slice(
+            AnimationHeader {
+                header: Header::rgba(65535, 65535),
+                frame_count: 0.into(),
+                loops: 0.into(),
+            }
+            .as_bytes(),
+        );
+        assert_eq!(data.len(), 22);
+
+        let (header, frames) = decode_frames(&data).unwrap();
+        assert_eq!(header.header.width.get(), 65535);
+        assert!(frames.is_empty());
+
+        // claiming a frame that isn't there
+        data[14..18].copy_from_slice(&1u32.to_be_bytes());
+        assert!(decode_frames(&data).is_none());
+
+        let invalid = Header {
+            width: 0.into(),
+            ..Header::rgba(1, 1)
+        };
+        assert!(Canvas::try_new(&invalid).is_none());
+    }
+
+    #[test]
+    fn delta_frames_round_trip() {
+        let (header, background) = noisy_image(23, 17);
+        let mut rnd = rng(9);
+
+        // frames changing a few pixels, a block, nothing, and everything
+        let mut frames = vec![background.clone()];
+        let mut frame = background.clone();
+        frame[30] = random_pixel(&mut rnd);
+        frame[200] = random_pixel(&mut rnd);
+        frames.push(frame.clone());
+        for row in 5..9 {
+            for x in 10..20 {
+                frame[row * 23 + x] = random_pixel(&mut rnd);
+            }
+        }
+        frames.push(frame.clone());
+        frames.push(frame.clone());
+        frames.push(noisy_image(23, 17).1.iter().rev().copied().collect());
+
+        let encode = |delta: bool| {
+            let mut encoder = AnimationEncoder::new(header).unwrap();
+            if delta {
+                encoder = encoder.with_delta_frames();
+            }
+            for (i, frame) in frames.iter().enumerate() {
+                encoder.push_frame(frame, 10 * i as u32).unwrap();
+            }
+            encoder.finish()
+        };
+
+        let (full, delta) = (encode(false), encode(true));
+        assert!(delta.len() < full.len());
+
+        for data in [&full, &delta] {
+            let (header, decoded) = decode_frames(data).unwrap();
+            assert_eq!(header.frame_count.get(), 5);
+            assert_eq!(decoded.len(), 5);
+            for (i, (decoded, frame)) in decoded.iter().zip(&frames).enumerate() {
+                assert!(decoded.pixels == *frame, "frame {}", i);
+                assert_eq!(decoded.delay_ms, 10 * i as u32);
+            }
+        }
+
+        // delta frames only cover what changed
+        let (_, reader) = AnimationReader::start(&delta).unwrap();
+        let rects: Vec<_> = reader
+            .map(|frame| {
+                let info = frame.info().unwrap();
+                (info.x, info.y, info.width, info.height)
+            })
+            .collect();
+        assert_eq!(
+            rects,
+            [
+                (0, 0, 23, 17),
+                (7, 1, 10, 8),
+                (10, 5, 10, 4),
+                (0, 0, 1, 1),
+                (0, 0, 23, 17)
+            ]
+        );
+    }
+
+    #[test]
+    fn regions_are_disposed_of_through_the_canvas() {
+        let (header, background) = noisy_image(8, 6);
+        let (_, b) = noisy_image(3, 2);
+        let c: Vec<_> = noisy_image(3, 3).1.iter().rev().copied().collect();
+        let d = [random_pixel(&mut rng(1))];
+
+        let region = |x, y, width, height, disposal| FrameInfo {
+            x,
+            y,
+            width,
+            height,
+            delay_ms: 40,
+            disposal,
+        };
+        let mut encoder = AnimationEncoder::new(header).unwrap();
+        encoder
+            .push_region(&region(0, 0, 8, 6, Disposal::Keep), &background)
+            .unwrap();
+        encoder
+            .push_region(&region(2, 1, 3, 2, Disposal::Background), &b)
+            .unwrap();
+        // overlapping the cleared rectangle, which is what gets restored
+        encoder
+            .push_region(&region(3, 2, 3, 3, Disposal::Previous), &c)
+            .unwrap();
+        encoder
+            .push_region(&region(5, 5, 1, 1, Disposal::Keep), &d)
+            .unwrap();
+        assert!(matches!(
+            encoder.push_region(&region(6, 0, 3, 1, Disposal::Keep), &[d[0]; 3]),
+            Err(EncodeError::FrameOutOfBounds)
+        ));
+        let data = encoder.finish();
+
+        let mut expected = Vec::new();
+        let mut canvas = background.clone();
+        expected.push(canvas.clone());
+        paste(&mut canvas, 8, 2, 1, 3, &b);
+        expected.push(canvas.clone());
+        paste(&mut canvas, 8, 2, 1, 3, &[CLEAR; 6]);
+        let before_c = canvas.clone();
+        paste(&mut canvas, 8, 3, 2, 3, &c);
+        expected.push(canvas.clone());
+        let mut canvas = before_c;
+        paste(&mut canvas, 8, 5, 5, 1, &d);
+        expected.push(canvas);
+
+        let (_, frames) = decode_frames(&data).unwrap();
+        assert_eq!(frames.len(), 4);
+        for (i, (frame, expected)) in frames.iter().zip(&expected).enumerate() {
+            assert!(frame.pixels == *expected, "frame {}", i);
+        }
+
+        // the same frames drawn straight onto a canvas
+        let mut canvas = Canvas::new(&header);
+        let (_, reader) = AnimationReader::start(&data).unwrap();
+        for (frame, expected) in reader.zip(&expected) {
+            let (_, pixels) = decode_to_vec(frame.data).unwrap();
+            assert!(canvas.draw(&frame.info().unwrap(), &pixels).unwrap() == expected.as_slice());
+        }
+        assert!(canvas
+            .draw(&region(0, 0, 2, 2, Disposal::Keep), &d)
+            .is_none());
+    }
+
+    #[test]
+    fn loops_are_written_to_the_header() {
+        let (header, pixels) = noisy_image(4, 4);
+        for (loops, expected) in [(None, 0), (Some(3), 3), (Some(0), 0)] {
+            let mut encoder = AnimationEncoder::new(header).unwrap();
+            if let Some(loops) = loops {
+                encoder = encoder.with_loops(loops);
+            }
+            encoder.push_frame(&pixels, 0).unwrap();
+
+            let (animation, frames) = decode_frames(&encoder.finish()).unwrap();
+            assert_eq!(animation.loops.get(), expected);
+            assert_eq!(animation.header.as_bytes(), header.as_bytes());
+            assert_eq!(frames.len(), 1);
+        }
+    }
+}
@@ -0,0 +1,385 @@
+//! Async QOI decoding & encoding, over either tokio's or the futures crate's IO traits.
+
+// this macro implements the async chunk reader, decoder & encoder. it expects `AsyncRead`, `AsyncWrite` and async `read`, `write_all` & `flush` helpers to be in scope.
+macro_rules! impl_async_io {
+    () => {
+        use crate::decoder::{ImageDecoder, SliceReader};
+        use crate::encoder::{EncodeError, Encoder, BLOCK_SIZE};
+        use crate::*;
+        use std::io;
+        use zerocopy::FromBytes;
+
+        /// Reads QOI operation chunks from an async reader, buffering it internally.
+        pub struct AsyncChunkReader<R: AsyncRead + Unpin> {
+            inner: R,
+            buf: Box<[u8; BLOCK_SIZE]>,
+            pos: usize,
+            filled: usize,
+            eof: bool,
+        }
+
+        impl<R: AsyncRead + Unpin> AsyncChunkReader<R> {
+            /// Reads the QOI magic and header, returning the header and a chunk reader if it's a valid QOI file.
+            pub async fn start(mut inner: R) -> io::Result<(Header, AsyncChunkReader<R>)> {
+                let mut start = [0u8; 14];
+                let mut filled = 0;
+                while filled < start.len() {
+                    match read(&mut inner, &mut start[filled..]).await? {
+                        0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                        n => filled += n,
+                    }
+                }
+
+                if start[0..4] != tags::QOI_MAGIC {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "invalid qoi magic",
+                    ));
+                }
+
+                let header = Header::read_from(&start[4..14]).unwrap();
+
+                Ok((
+                    header,
+                    AsyncChunkReader {
+                        inner,
+                        buf: Box::new([0; BLOCK_SIZE]),
+                        pos: 0,
+                        filled: 0,
+                        eof: false,
+                    },
+                ))
+            }
+
+            /// Transforms reader into an async image decoder, decoding as many pixels as `header` holds.
+            pub fn into_decoder(self, header: &Header) -> AsyncImageDecoder<R> {
+                AsyncImageDecoder {
+                    inner: self,
+                    decoder: ImageDecoder::new(core::iter::empty()),
+                    remaining: header.width.get() as u64 * header.height.get() as u64,
+                }
+            }
+
+            /// Reads the next chunk, returning None once the end of the stream is reached.
+            pub async fn next_chunk(&mut self) -> io::Result<Option<Chunk>> {
+                // the longest chunk & the end marker both fit in 8 bytes, so keep at least that much buffered
+                if self.filled - self.pos < tags::BYTESTREAM_END.len() && !self.eof {
+                    self.fill().await?;
+                }
+
+                let mut reader = SliceReader::over_chunks(&self.buf[self.pos..self.filled]);
+                let chunk = reader.next();
+                self.pos += reader.position();
+
+                // stop for good at the end of the stream, rather than reading past the end marker
+                if chunk.is_none() {
+                    self.pos = self.filled;
+                    self.eof = true;
+                }
+
+                Ok(chunk)
+            }
+
+            async fn fill(&mut self) -> io::Result<()> {
+                self.buf.copy_within(self.pos..self.filled, 0);
+                self.filled -= self.pos;
+                self.pos = 0;
+
+                while self.filled < tags::BYTESTREAM_END.len() {
+                    match read(&mut self.inner, &mut self.buf[self.filled..]).await? {
+                        0 => {
+                            self.eof = true;
+                            break;
+                        }
+                        n => self.filled += n,
+                    }
+                }
+
+                Ok(())
+            }
+        }
+
+        /// An async QOI decoder, decoding chunks from an [AsyncChunkReader] with an [ImageDecoder].
+        pub struct AsyncImageDecoder<R: AsyncRead + Unpin> {
+            inner: AsyncChunkReader<R>,
+            decoder: ImageDecoder<core::iter::Empty<Chunk>>,
+            // pixels left in the image, according to its header
+            remaining: u64,
+        }
+
+        impl<R: AsyncRead + Unpin> AsyncImageDecoder<R> {
+            /// Reads the QOI magic and header, returning the header and a decoder if it's a valid QOI file.
+            pub async fn start(inner: R) -> io::Result<(Header, AsyncImageDecoder<R>)> {
+                let (header, reader) = AsyncChunkReader::start(inner).await?;
+                let decoder = reader.into_decoder(&header);
+                Ok((header, decoder))
+            }
+
+            /// Decodes the next pixel, returning None once every pixel of the image has been decoded.
+            /// Fails with [io::ErrorKind::UnexpectedEof] if the stream ends before the image does.
+            pub async fn next_pixel(&mut self) -> io::Result<Option<RgbaPixel>> {
+                if self.remaining == 0 {
+                    return Ok(None);
+                }
+
+                let pixel = match self.decoder.next_run_pixel() {
+                    Some(pixel) => pixel,
+                    None => match self.inner.next_chunk().await? {
+                        Some(chunk) => self.decoder.push_chunk(chunk),
+                        None => {
+                            self.remaining = 0;
+                            return Err(io::Error::new(
+                                io::ErrorKind::UnexpectedEof,
+                                "qoi stream ended early",
+                            ));
+                        }
+                    },
+                };
+
+                self.remaining -= 1;
+                Ok(Some(pixel))
+            }
+
+            /// Decodes the rest of the image into a Vec of pixels.
+            pub async fn read_to_vec(mut self) -> io::Result<Vec<RgbaPixel>> {
+                let mut out = Vec::new();
+                while let Some(pixel) = self.next_pixel().await? {
+                    out.push(pixel);
+                }
+
+                Ok(out)
+            }
+        }
+
+        /// Writes out an iterator over RgbaPixels (or things that can be converted into RgbaPixels) as QOI bytes into an async writer.
+        pub async fn write_image<T, I, W>(
+            mut encoder: Encoder,
+            image: I,
+            out: &mut W,
+        ) -> Result<(), EncodeError>
+        where
+            T: Into<RgbaPixel>,
+            I: IntoIterator<Item = T>,
+            W: AsyncWrite + Unpin,
+        {
+            let mut block: ArrayVec<u8, BLOCK_SIZE> = ArrayVec::new_const();
+            block.extend(tags::QOI_MAGIC);
+            block.extend(encoder.header.as_bytes().iter().copied());
+
+            for pixel in image {
+                for chunk in encoder.process_pixel(pixel.into())? {
+                    if chunk.write_to_arrayvec(&mut block).is_none() {
+                        write_all(out, &block).await?;
+                        block.clear();
+                        chunk.write_to_arrayvec(&mut block);
+                    }
+                }
+            }
+
+            encoder.finish()?;
+
+            if block.try_extend_from_slice(&tags::BYTESTREAM_END).is_err() {
+                write_all(out, &block).await?;
+                block.clear();
+                block.extend(tags::BYTESTREAM_END);
+            }
+
+            write_all(out, &block).await?;
+            flush(out).await?;
+
+            Ok(())
+        }
+    };
+}
+
+#[cfg(feature = "tokio")]
+pub mod tokio {
+    //! Async decoding & encoding over [tokio::io::AsyncRead] and [tokio::io::AsyncWrite].
+
+    use ::tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+    async fn read<R: AsyncRead + Unpin>(r: &mut R, buf: &mut [u8]) -> std::io::Result<usize> {
+        r.read(buf).await
+    }
+
+    async fn write_all<W: AsyncWrite + Unpin>(w: &mut W, buf: &[u8]) -> std::io::Result<()> {
+        w.write_all(buf).await
+    }
+
+    async fn flush<W: AsyncWrite + Unpin>(w: &mut W) -> std::io::Result<()> {
+        w.flush().await
+    }
+
+    impl_async_io!();
+}
+
+#[cfg(feature = "futures-io")]
+pub mod futures {
+    //! Async decoding & encoding over [futures_io::AsyncRead] and [futures_io::AsyncWrite].
+
+    use ::futures_io::{AsyncRead, AsyncWrite};
+    use core::future::poll_fn;
+    use core::pin::Pin;
+
+    async fn read<R: AsyncRead + Unpin>(r: &mut R, buf: &mut [u8]) -> std::io::Result<usize> {
+        poll_fn(|cx| Pin::new(&mut *r).poll_read(cx, buf)).await
+    }
+
+    async fn write_all<W: AsyncWrite + Unpin>(w: &mut W, mut buf: &[u8]) -> std::io::Result<()> {
+        while !buf.is_empty() {
+            match poll_fn(|cx| Pin::new(&mut *w).poll_write(cx, buf)).await? {
+                0 => return Err(std::io::ErrorKind::WriteZero.into()),
+                n => buf = &buf[n..],
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn flush<W: AsyncWrite + Unpin>(w: &mut W) -> std::io::Result<()> {
+        poll_fn(|cx| Pin::new(&mut *w).poll_flush(cx)).await
+    }
+
+    impl_async_io!();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encoder::Encoder;
+    use crate::test_utils::noisy_image;
+    use crate::RgbaPixel;
+
+    #[cfg(feature = "tokio")]
+    #[::tokio::test]
+    async fn tokio_duplex_round_trip() {
+        use super::tokio::{write_image, AsyncImageDecoder};
+
+        let (header, pixels) = noisy_image(67, 45);
+        // a small buffer, so that reads & writes wait on each other
+        let (mut writer, reader) = ::tokio::io::duplex(64);
+
+        let encode = async {
+            write_image(
+                Encoder::new(header).unwrap(),
+                pixels.iter().copied(),
+                &mut writer,
+            )
+            .await
+            .unwrap();
+        };
+        let decode = async {
+            let (decoded_header, decoder) = AsyncImageDecoder::start(reader).await.unwrap();
+            assert_eq!(decoded_header.width.get(), header.width.get());
+            assert_eq!(decoded_header.height.get(), header.height.get());
+            decoder.read_to_vec().await.unwrap()
+        };
+
+        let ((), decoded) = ::tokio::join!(encode, decode);
+        assert_eq!(decoded, pixels);
+
+        let mut out = Vec::new();
+        write_image(
+            Encoder::new(header).unwrap(),
+            pixels.iter().copied(),
+            &mut out,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            out,
+            Encoder::new(header).unwrap().image_to_vec(pixels).unwrap()
+        );
+    }
+
+    #[cfg(feature = "futures-io")]
+    #[test]
+    fn futures_round_trip() {
+        use super::futures::{write_image, AsyncImageDecoder};
+        use core::pin::Pin;
+        use core::task::{Context, Poll};
+
+        // hands out a few bytes at a time, like a slow stream would
+        struct Trickle<'a>(&'a [u8]);
+
+        impl futures_io::AsyncRead for Trickle<'_> {
+            fn poll_read(
+                mut self: Pin<&mut Self>,
+                _: &mut Context<'_>,
+                buf: &mut [u8],
+            ) -> Poll<std::io::Result<usize>> {
+                let n = buf.len().min(self.0.len()).min(7);
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Poll::Ready(Ok(n))
+            }
+        }
+
+        let (header, pixels) = noisy_image(67, 45);
+        futures::executor::block_on(async {
+            let mut out = Vec::new();
+            write_image(
+                Encoder::new(header).unwrap(),
+                pixels.iter().copied(),
+                &mut out,
+            )
+            .await
+            .unwrap();
+            assert_eq!(
+                out,
+                Encoder::new(header)
+                    .unwrap()
+                    .image_to_vec(pixels.iter().copied())
+                    .unwrap()
+            );
+
+            let (_, decoder) = AsyncImageDecoder::start(Trickle(&out)).await.unwrap();
+            assert_eq!(decoder.read_to_vec().await.unwrap(), pixels);
+        });
+    }
+
+    // the test image, cut off halfway through its chunks, & with its header claiming one row less than it holds
+    fn damaged_files() -> (Vec<u8>, Vec<u8>, Vec<RgbaPixel>) {
+        let (header, pixels) = noisy_image(67, 45);
+        let file = Encoder::new(header)
+            .unwrap()
+            .image_to_vec(pixels.iter().copied())
+            .unwrap();
+
+        let truncated = file[..file.len() / 2].to_vec();
+        let mut shortened = file;
+        shortened[8..12].copy_from_slice(&44u32.to_be_bytes());
+
+        (truncated, shortened, pixels[..67 * 44].to_vec())
+    }
+
+    #[cfg(feature = "tokio")]
+    #[::tokio::test]
+    async fn tokio_reports_truncation() {
+        use super::tokio::AsyncImageDecoder;
+
+        let (truncated, shortened, expected) = damaged_files();
+
+        let (_, decoder) = AsyncImageDecoder::start(&truncated[..]).await.unwrap();
+        let error = decoder.read_to_vec().await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+
+        let (_, decoder) = AsyncImageDecoder::start(&shortened[..]).await.unwrap();
+        assert_eq!(decoder.read_to_vec().await.unwrap(), expected);
+    }
+
+    #[cfg(feature = "futures-io")]
+    #[test]
+    fn futures_reports_truncation() {
+        use super::futures::AsyncImageDecoder;
+
+        let (truncated, shortened, expected) = damaged_files();
+        futures::executor::block_on(async {
+            let (_, decoder) = AsyncImageDecoder::start(&truncated[..]).await.unwrap();
+            let error = decoder.read_to_vec().await.unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+
+            let (_, decoder) = AsyncImageDecoder::start(&shortened[..]).await.unwrap();
+            assert_eq!(decoder.read_to_vec().await.unwrap(), expected);
+        });
+    }
+}
@@ -0,0 +1,249 @@
+//! Batch conversion of image files to QOI, decoding them with the image crate. Files are converted in parallel with the rayon feature.
+
+use crate::encoder::{EncodeError, Encoder, QoiWriter};
+use crate::*;
+use std::fmt;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+/// An error that occurred while converting a file.
+#[derive(Debug)]
+pub enum BatchError {
+    /// The input image couldn't be read or decoded.
+    Image(image::ImageError),
+    /// The image couldn't be encoded or written out.
+    Encode(EncodeError),
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BatchError::Image(e) => write!(f, "couldn't read image: {}", e),
+            BatchError::Encode(e) => write!(f, "couldn't write qoi: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BatchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BatchError::Image(e) => Some(e),
+            BatchError::Encode(e) => Some(e),
+        }
+    }
+}
+
+impl From<image::ImageError> for BatchError {
+    fn from(e: image::ImageError) -> BatchError {
+        BatchError::Image(e)
+    }
+}
+
+impl From<EncodeError> for BatchError {
+    fn from(e: EncodeError) -> BatchError {
+        BatchError::Encode(e)
+    }
+}
+
+impl From<std::io::Error> for BatchError {
+    fn from(e: std::io::Error) -> BatchError {
+        BatchError::Encode(EncodeError::Io(e))
+    }
+}
+
+/// A file to convert.
+#[derive(Clone, Debug)]
+pub struct Job {
+    pub input: PathBuf,
+    pub output: PathBuf,
+}
+
+/// The result of a successful conversion.
+#[derive(Clone, Copy, Debug)]
+pub struct Converted {
+    pub width: u32,
+    pub height: u32,
+    pub channels: u8,
+    /// The size of the input file, in bytes.
+    pub input_size: u64,
+    /// The size of the QOI file, in bytes.
+    pub output_size: u64,
+}
+
+impl Converted {
+    /// The size of the raw pixels, width * height * channels.
+    pub fn raw_size(&self) -> u64 {
+        self.width as u64 * self.height as u64 * self.channels as u64
+    }
+}
+
+/// Converts an image file to QOI. Images with an alpha channel are encoded as RGBA, others as RGB.
+///
+/// The QOI file is written next to `output` with a `.part` suffix, then renamed to `output` once complete,
+/// so a failed conversion doesn't leave a partial file behind.
+pub fn convert_file(input: &Path, output: &Path) -> Result<Converted, BatchError> {
+    let input_size = std::fs::metadata(input)?.len();
+
+    let image = image::open(input)?;
+    let channels = if image.color().has_alpha() { 4 } else { 3 };
+    let image = image.into_rgba8();
+    let (width, height) = image.dimensions();
+
+    let header = Header {
+        channels,
+        ..Header::rgba(width, height)
+    };
+
+    let mut partial = output.as_os_str().to_owned();
+    partial.push(".part");
+    let partial = PathBuf::from(partial);
+
+    let written = write_qoi(header, image.as_raw(), &partial).and_then(|size| {
+        std::fs::rename(&partial, output)?;
+        Ok(size)
+    });
+    let output_size = match written {
+        Ok(size) => size,
+        Err(e) => {
+            let _ = std::fs::remove_file(&partial);
+            return Err(e);
+        }
+    };
+
+    Ok(Converted {
+        width,
+        height,
+        channels,
+        input_size,
+        output_size,
+    })
+}
+
+// encodes RGBA bytes into a new file, returning its size
+fn write_qoi(header: Header, rgba: &[u8], path: &Path) -> Result<u64, BatchError> {
+    let mut writer = QoiWriter::new(Encoder::new(header)?, File::create(path)?);
+    writer.write_slice(RgbaPixel::slice_from_bytes(rgba).unwrap())?;
+    Ok(writer.finish()?.metadata()?.len())
+}
+
+/// Converts a batch of image files to QOI, returning the result of each conversion in order.
+pub fn convert_batch(jobs: &[Job]) -> Vec<Result<Converted, BatchError>> {
+    #[cfg(feature = "rayon")]
+    let jobs = jobs.par_iter();
+    #[cfg(not(feature = "rayon"))]
+    let jobs = jobs.iter();
+
+    jobs.map(|job| convert_file(&job.input, &job.output))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::noisy_image;
+
+    // a scratch directory of its own for each test, as tests run in parallel
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("teeny-qoi-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn batch_reports_each_file() {
+        let dir = scratch_dir("batch");
+        let (header, pixels) = noisy_image(37, 21);
+        let rgba: Vec<u8> = pixels.as_bytes().to_vec();
+        let png = dir.join("noisy.png");
+        image::save_buffer(&png, &rgba, 37, 21, image::ColorType::Rgba8).unwrap();
+
+        let broken = dir.join("broken.png");
+        std::fs::write(&broken, b"\x89PNG\r\n\x1a\nnot really").unwrap();
+
+        let jobs = [
+            Job {
+                input: png.clone(),
+                output: dir.join("noisy.qoi"),
+            },
+            Job {
+                input: broken,
+                output: dir.join("broken.qoi"),
+            },
+            Job {
+                input: png.clone(),
+                output: dir.join("missing").join("noisy.qoi"),
+            },
+        ];
+        let results = convert_batch(&jobs);
+
+        let converted = results[0].as_ref().unwrap();
+        let qoi = std::fs::read(&jobs[0].output).unwrap();
+        assert_eq!(
+            (converted.width, converted.height, converted.channels),
+            (37, 21, 4)
+        );
+        assert_eq!(converted.raw_size(), 37 * 21 * 4);
+        assert_eq!(converted.input_size, std::fs::metadata(&png).unwrap().len());
+        assert_eq!(converted.output_size, qoi.len() as u64);
+        assert_eq!(
+            qoi,
+            Encoder::new(header)
+                .unwrap()
+                .pixels_to_vec(&pixels)
+                .unwrap()
+        );
+
+        assert!(matches!(results[1], Err(BatchError::Image(_))));
+        assert!(matches!(
+            results[2],
+            Err(BatchError::Encode(EncodeError::Io(_)))
+        ));
+
+        // only the inputs & the one converted file are left
+        let mut left: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["broken.png", "noisy.png", "noisy.qoi"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_writes_leave_no_partial_file() {
+        let dir = scratch_dir("partial");
+        let png = dir.join("opaque.png");
+        image::save_buffer(&png, &[10, 20, 30].repeat(64), 8, 8, image::ColorType::Rgb8).unwrap();
+
+        // a directory in the way of the rename: the QOI file is complete, but can't be moved into place
+        let output = dir.join("opaque.qoi");
+        std::fs::create_dir(&output).unwrap();
+        std::fs::write(output.join("keep"), b"").unwrap();
+
+        assert!(matches!(
+            convert_file(&png, &output),
+            Err(BatchError::Encode(EncodeError::Io(_)))
+        ));
+        assert!(!dir.join("opaque.qoi.part").exists());
+
+        std::fs::remove_dir_all(&output).unwrap();
+        let converted = convert_file(&png, &output).unwrap();
+        assert_eq!(converted.channels, 3);
+        let (header, decoded) = decoder::decode_to_vec(&std::fs::read(&output).unwrap()).unwrap();
+        assert_eq!(header.channels, 3);
+        assert!(decoded.iter().all(|&p| p
+            == RgbaPixel {
+                r: 10,
+                g: 20,
+                b: 30,
+                a: 255
+            }));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
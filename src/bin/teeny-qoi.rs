@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use teeny_qoi::batch::{self, Job};
+
+const USAGE: &str = "usage: teeny-qoi batch <output dir> <input files...>";
+
+fn run_batch(args: &[String]) -> ExitCode {
+    let (output_dir, inputs) = match args {
+        [output_dir, inputs @ ..] if !inputs.is_empty() => (PathBuf::from(output_dir), inputs),
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // every input gets its own output, named after it; refuse to start otherwise,
+    // as jobs run in parallel & would overwrite each other
+    let mut jobs: Vec<Job> = Vec::with_capacity(inputs.len());
+    let mut outputs: HashMap<PathBuf, usize> = HashMap::new();
+    for input in inputs {
+        let input = PathBuf::from(input);
+        let output = match input.file_name() {
+            Some(name) => output_dir.join(Path::new(name).with_extension("qoi")),
+            None => {
+                eprintln!("{}: not a file name", input.display());
+                return ExitCode::FAILURE;
+            }
+        };
+
+        if let Some(&other) = outputs.get(&output) {
+            eprintln!(
+                "{} and {} would both be written to {}",
+                jobs[other].input.display(),
+                input.display(),
+                output.display()
+            );
+            return ExitCode::FAILURE;
+        }
+
+        outputs.insert(output.clone(), jobs.len());
+        jobs.push(Job { input, output });
+    }
+
+    let mut failed = 0;
+    for (job, result) in jobs.iter().zip(batch::convert_batch(&jobs)) {
+        match result {
+            Ok(converted) => println!(
+                "{} -> {}: {}x{}, {} bytes ({:.1}% of raw, {:.1}% of input)",
+                job.input.display(),
+                job.output.display(),
+                converted.width,
+                converted.height,
+                converted.output_size,
+                converted.output_size as f64 * 100.0 / converted.raw_size() as f64,
+                converted.output_size as f64 * 100.0 / converted.input_size as f64,
+            ),
+            Err(e) => {
+                failed += 1;
+                eprintln!("{}: {}", job.input.display(), e);
+            }
+        }
+    }
+
+    if failed > 0 {
+        eprintln!("{} of {} files failed", failed, jobs.len());
+        return ExitCode::FAILURE;
+    }
+
+    ExitCode::SUCCESS
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.first().map(String::as_str) {
+        Some("batch") => run_batch(&args[1..]),
+        _ => {
+            eprintln!("{}", USAGE);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! Compressed QOI files, wrapping a QOI stream in a second stage of LZ4 or zstd compression, both through pure Rust implementations.
+//!
+//! A compressed file is made of:
+//! - the `qoiz` magic
+//! - the [Compression] method, as a byte
+//! - the size of the QOI stream, as a big endian u64
+//! - the compressed QOI stream.
+//!
+//! [unwrap_compressed] detects compressed files, handing back the QOI stream to read with [SliceReader](crate::decoder::SliceReader).
+
+use crate::decoder;
+use crate::*;
+use std::borrow::Cow;
+use std::io::Read;
+use zerocopy::U64;
+
+/// Magic bytes for compressed QOI files.
+pub const COMPRESSED_MAGIC: [u8; 4] = [b'q', b'o', b'i', b'z'];
+
+/// The compression applied to a QOI stream.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Compression {
+    /// LZ4 block compression: fast, with a modest gain.
+    Lz4 = 1,
+    /// A zstd frame: slower, with a larger gain.
+    Zstd = 2,
+}
+
+impl Compression {
+    /// Reads a compression method from its byte, returning None if it's unknown.
+    pub fn from_u8(value: u8) -> Option<Compression> {
+        match value {
+            1 => Some(Compression::Lz4),
+            2 => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+}
+
+/// Compresses a QOI stream, such as the output of [Encoder::image_to_vec](crate::encoder::Encoder::image_to_vec).
+pub fn compress(qoi: &[u8], compression: Compression) -> Vec<u8> {
+    let compressed = match compression {
+        Compression::Lz4 => lz4_flex::block::compress(qoi),
+        Compression::Zstd => {
+            ruzstd::encoding::compress_to_vec(qoi, ruzstd::encoding::CompressionLevel::Fastest)
+        }
+    };
+
+    let mut out = Vec::with_capacity(13 + compressed.len());
+    out.extend_from_slice(&COMPRESSED_MAGIC);
+    out.push(compression as u8);
+    out.extend_from_slice(U64::<BigEndian>::new(qoi.len() as u64).as_bytes());
+    out.extend_from_slice(&compressed);
+    out
+}
+
+/// Decompresses a compressed QOI file back into a QOI stream. Returns None if the file is invalid.
+pub fn decompress(data: &[u8]) -> Option<Vec<u8>> {
+    if *data.get(0..4)? != COMPRESSED_MAGIC {
+        return None;
+    }
+
+    let compression = Compression::from_u8(*data.get(4)?)?;
+    let size = usize::try_from(U64::<BigEndian>::read_from(data.get(5..13)?)?.get()).ok()?;
+    let compressed = &data[13..];
+
+    let mut out = Vec::new();
+    match compression {
+        Compression::Lz4 => {
+            // an lz4 sequence of n bytes expands to at most 255 * n, so larger sizes can't be right
+            if size > compressed.len().saturating_mul(255) {
+                return None;
+            }
+
+            // the size is untrusted, so allocating it may fail
+            out.try_reserve_exact(size).ok()?;
+            out.resize(size, 0);
+            let written = lz4_flex::block::decompress_into(compressed, &mut out).ok()?;
+            out.truncate(written);
+        }
+        Compression::Zstd => {
+            // zstd frames can expand almost without bound, so the output grows as it's decoded instead of trusting the size,
+            // reading one byte past it to catch streams that are too long
+            let decoder = ruzstd::decoding::StreamingDecoder::new(compressed).ok()?;
+            decoder
+                .take((size as u64).saturating_add(1))
+                .read_to_end(&mut out)
+                .ok()?;
+        }
+    }
+
+    (out.len() == size).then_some(out)
+}
+
+/// Hands back the QOI stream held in `data`: decompressed if it's a compressed QOI file, as-is otherwise. Returns None if decompression fails.
+pub fn unwrap_compressed(data: &[u8]) -> Option<Cow<'_, [u8]>> {
+    if data.starts_with(&COMPRESSED_MAGIC) {
+        decompress(data).map(Cow::Owned)
+    } else {
+        Some(Cow::Borrowed(data))
+    }
+}
+
+/// Decodes a QOI file, compressed or not, into a Vec of pixels.
+pub fn decode_to_vec(data: &[u8]) -> Option<(Header, Vec<RgbaPixel>)> {
+    decoder::decode_to_vec(&unwrap_compressed(data)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::Encoder;
+    use crate::test_utils::noisy_image;
+
+    #[test]
+    fn round_trips_highly_compressible_streams() {
+        let qoi = vec![0u8; 1 << 20];
+        for compression in [Compression::Lz4, Compression::Zstd] {
+            let compressed = compress(&qoi, compression);
+            assert_eq!(decompress(&compressed).unwrap(), qoi);
+        }
+    }
+
+    #[test]
+    fn rejects_sizes_lz4_cant_reach() {
+        let mut compressed = compress(&[0u8; 1000], Compression::Lz4);
+        compressed[5..13].copy_from_slice(&(1u64 << 40).to_be_bytes());
+        assert!(decompress(&compressed).is_none());
+    }
+
+    #[test]
+    fn zstd_ignores_huge_sizes() {
+        let qoi = vec![0u8; 1000];
+        let compressed = compress(&qoi, Compression::Zstd);
+
+        for size in [1u64 << 40, u64::MAX, 999, 1001] {
+            let mut compressed = compressed.clone();
+            compressed[5..13].copy_from_slice(&size.to_be_bytes());
+            assert!(decompress(&compressed).is_none(), "{}", size);
+        }
+    }
+
+    #[test]
+    fn decodes_compressed_and_plain_images() {
+        let (header, pixels) = noisy_image(67, 45);
+        let qoi = Encoder::new(header)
+            .unwrap()
+            .pixels_to_vec(&pixels)
+            .unwrap();
+
+        for compression in [Compression::Lz4, Compression::Zstd] {
+            let compressed = compress(&qoi, compression);
+            assert!(
+                matches!(unwrap_compressed(&compressed), Some(Cow::Owned(stream)) if stream == qoi)
+            );
+
+            let (decoded_header, decoded) = decode_to_vec(&compressed).unwrap();
+            assert_eq!(decoded_header.as_bytes(), header.as_bytes());
+            assert!(decoded == pixels, "{:?}", compression);
+
+            let mut unknown = compressed.clone();
+            unknown[4] = 3;
+            assert!(unwrap_compressed(&unknown).is_none());
+            assert!(decode_to_vec(&compressed[..compressed.len() / 2]).is_none());
+        }
+
+        // plain QOI files are passed through without a copy
+        match unwrap_compressed(&qoi) {
+            Some(Cow::Borrowed(stream)) => assert!(core::ptr::eq(stream, qoi.as_slice())),
+            other => panic!("{:?}", other.map(|stream| stream.len())),
+        }
+        assert!(decode_to_vec(&qoi).unwrap().1 == pixels);
+    }
+}
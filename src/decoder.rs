@@ -3,7 +3,12 @@
 use crate::*;
 use zerocopy::FromBytes;
 
+#[cfg(feature = "std")]
+use crate::encoder::BLOCK_SIZE;
+
 /// Simple abstraction over a slice to help with reading
+///
+/// Iterating over it yields chunks one at a time; [SliceReader::decode_into] decodes the whole image at once, and is much faster.
 pub struct SliceReader<'a> {
     inner: &'a [u8],
     cursor: usize,
@@ -12,20 +17,65 @@ pub struct SliceReader<'a> {
 impl<'a> SliceReader<'a> {
     /// Initializes the reader, returning the QOI Header and a Reader struct if it's a valid QOI file.
     pub fn start(inner: &'a [u8]) -> Option<(Header, SliceReader<'a>)> {
-        if inner[0..4] != tags::QOI_MAGIC {
+        if *inner.get(0..4)? != tags::QOI_MAGIC {
             return None;
         };
 
-        let header = Header::read_from(&inner[4..14])?;
+        let header = Header::read_from(inner.get(4..14)?)?;
 
         Some((header, SliceReader { cursor: 14, inner }))
     }
 
+    /// Builds a reader over raw chunk data, without a magic or header.
+    #[cfg(feature = "std")]
+    pub(crate) fn over_chunks(inner: &'a [u8]) -> SliceReader<'a> {
+        SliceReader { inner, cursor: 0 }
+    }
+
+    /// The position of the next chunk in the underlying slice.
+    #[cfg(any(feature = "alloc", feature = "std"))]
+    pub(crate) fn position(&self) -> usize {
+        self.cursor
+    }
+
     /// Transforms reader into an image decoder.
     pub fn into_decoder(self) -> ImageDecoder<SliceReader<'a>> {
         ImageDecoder::new(self)
     }
-    
+
+    /// Decodes pixels straight into `out`, until it is full. Returns None if the data, or the chunks before the end marker, run out first.
+    ///
+    /// This skips building [Chunk]s altogether, and only checks bounds once per block of chunks.
+    pub fn decode_into(self, out: &mut [RgbaPixel]) -> Option<()> {
+        let data = &self.inner[self.cursor..];
+        // the end marker decodes as pixels, so leave it out: reaching it means the stream is shorter than `out`
+        let data = data.strip_suffix(&tags::BYTESTREAM_END).unwrap_or(data);
+        let mut state = FastState {
+            previous: u32::from_le_bytes([0, 0, 0, 255]),
+            previously_seen: [0; 64],
+            written: 0,
+        };
+        let mut pos = 0;
+
+        while state.written < out.len() {
+            let consumed = if data.len() - pos >= BLOCK_BYTES {
+                state.decode_block(array_ref!(data, pos, BLOCK_BYTES), out)
+            } else {
+                // pad out the last few bytes, making sure we don't decode past them
+                let mut padded = [0u8; BLOCK_BYTES];
+                padded[..data.len() - pos].copy_from_slice(&data[pos..]);
+                state.decode_block(&padded, out)
+            };
+
+            pos += consumed;
+            if pos > data.len() {
+                return None;
+            }
+        }
+
+        Some(())
+    }
+
     fn peek_n<const N: usize>(&self) -> Option<&'a [u8; N]> {
         if self.cursor + N > self.inner.len() {
             return None;
@@ -73,14 +123,12 @@ impl<'a> Iterator for SliceReader<'a> {
 
                 return Some(Chunk::Rgba { r, g, b, a });
             }
-            0 => {
-                if self
-                    .peek_n::<7>()
-                    .filter(|b| b[..] == tags::BYTESTREAM_END[1..])
-                    .is_some()
-                {
-                    return None;
-                }
+            0 if self
+                .peek_n::<7>()
+                .filter(|b| b[..] == tags::BYTESTREAM_END[1..])
+                .is_some() =>
+            {
+                return None;
             }
             _ => (),
         };
@@ -109,6 +157,370 @@ impl<'a> Iterator for SliceReader<'a> {
     }
 }
 
+/// Reads QOI operation chunks from a [std::io::Read], buffering it internally.
+///
+/// An IO error ends the iterator; it can be retrieved with [StreamReader::take_error].
+#[cfg(feature = "std")]
+pub struct StreamReader<R: std::io::Read> {
+    inner: R,
+    buf: Box<[u8; BLOCK_SIZE]>,
+    pos: usize,
+    filled: usize,
+    eof: bool,
+    error: Option<std::io::Error>,
+}
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read> StreamReader<R> {
+    /// Reads the QOI magic and header, returning the header and a reader if it's a valid QOI file.
+    pub fn start(mut inner: R) -> std::io::Result<(Header, StreamReader<R>)> {
+        let mut start = [0u8; 14];
+        inner.read_exact(&mut start)?;
+
+        if start[0..4] != tags::QOI_MAGIC {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "invalid qoi magic",
+            ));
+        }
+
+        let header = Header::read_from(&start[4..14]).unwrap();
+
+        Ok((
+            header,
+            StreamReader {
+                inner,
+                buf: Box::new([0; BLOCK_SIZE]),
+                pos: 0,
+                filled: 0,
+                eof: false,
+                error: None,
+            },
+        ))
+    }
+
+    /// Transforms reader into an image decoder.
+    pub fn into_decoder(self) -> ImageDecoder<StreamReader<R>> {
+        ImageDecoder::new(self)
+    }
+
+    /// Takes the IO error that ended the iterator, if there was one.
+    pub fn take_error(&mut self) -> Option<std::io::Error> {
+        self.error.take()
+    }
+
+    fn fill(&mut self) -> std::io::Result<()> {
+        self.buf.copy_within(self.pos..self.filled, 0);
+        self.filled -= self.pos;
+        self.pos = 0;
+
+        while self.filled < tags::BYTESTREAM_END.len() {
+            match self.inner.read(&mut self.buf[self.filled..]) {
+                Ok(0) => {
+                    self.eof = true;
+                    break;
+                }
+                Ok(n) => self.filled += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read> Iterator for StreamReader<R> {
+    type Item = Chunk;
+
+    fn next(&mut self) -> Option<Chunk> {
+        // the longest chunk & the end marker both fit in 8 bytes, so keep at least that much buffered
+        if self.filled - self.pos < tags::BYTESTREAM_END.len() && !self.eof {
+            if let Err(e) = self.fill() {
+                self.error = Some(e);
+                return None;
+            }
+        }
+
+        let mut reader = SliceReader::over_chunks(&self.buf[self.pos..self.filled]);
+        let chunk = reader.next();
+        self.pos += reader.position();
+
+        // stop for good at the end of the stream, rather than reading past the end marker
+        if chunk.is_none() {
+            self.pos = self.filled;
+            self.eof = true;
+        }
+
+        chunk
+    }
+}
+
+// checks that `chunk_bytes` bytes of chunks can hold `count` pixels, at most 62 per byte with runs
+#[cfg(any(feature = "alloc", feature = "std"))]
+pub(crate) fn holds_pixels(count: u64, chunk_bytes: usize) -> bool {
+    count <= 62 * chunk_bytes as u64
+}
+
+/// Allocates room for `count` pixels decoded from `chunk_bytes` bytes of chunks.
+/// Returns None if the chunks can't hold that many pixels or the allocation fails, as the count comes from an untrusted header.
+#[cfg(any(feature = "alloc", feature = "std"))]
+pub(crate) fn pixel_buffer<T>(count: u64, chunk_bytes: usize) -> Option<Vec<T>> {
+    if !holds_pixels(count, chunk_bytes) {
+        return None;
+    }
+
+    let mut pixels = Vec::new();
+    pixels
+        .try_reserve_exact(usize::try_from(count).ok()?)
+        .ok()?;
+    Some(pixels)
+}
+
+/// Decodes a QOI file into a Vec of pixels, using [SliceReader::decode_into]. Returns None if the file is invalid or truncated.
+#[cfg(any(feature = "alloc", feature = "std"))]
+pub fn decode_to_vec(data: &[u8]) -> Option<(Header, Vec<RgbaPixel>)> {
+    let (header, reader) = SliceReader::start(data)?;
+    if !header.is_valid() {
+        return None;
+    }
+
+    let count = header.pixel_count() as usize;
+    let mut pixels = pixel_buffer(count as u64, data.len() - 14)?;
+    pixels.resize(
+        count,
+        RgbaPixel {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 0,
+        },
+    );
+
+    reader.decode_into(&mut pixels)?;
+
+    Some((header, pixels))
+}
+
+/// Decodes only the pixels of a QOI file inside a rectangle, returning them along with a header for the cropped image.
+/// Returns None if the file is invalid, or the rectangle is empty or doesn't fit in the image.
+///
+/// Runs are skipped over without decoding their pixels one by one, and decoding stops after the last row of the rectangle.
+#[cfg(any(feature = "alloc", feature = "std"))]
+pub fn decode_region(
+    data: &[u8],
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+) -> Option<(Header, Vec<RgbaPixel>)> {
+    let (header, reader) = SliceReader::start(data)?;
+
+    if width == 0
+        || height == 0
+        || x.checked_add(width)? > header.width.get()
+        || y.checked_add(height)? > header.height.get()
+    {
+        return None;
+    }
+
+    let image_width = header.width.get() as usize;
+    let (x, y, width, height) = (x as usize, y as usize, width as usize, height as usize);
+
+    // the position right after the last pixel of the region
+    let end = (y + height - 1) * image_width + x + width;
+    if !holds_pixels(end as u64, data.len() - 14) {
+        return None;
+    }
+
+    let mut out = pixel_buffer((width * height) as u64, data.len() - 14)?;
+    let mut decoder = reader.into_decoder();
+    let mut position = 0;
+
+    while position < end {
+        let chunk = decoder.inner.next()?;
+        let pixel = decoder.push_chunk(chunk);
+        let span_end = position + 1 + mem::take(&mut decoder.run) as usize;
+
+        // a run may cross rows, so copy the part of it inside the region row by row
+        while position < span_end {
+            let row = position / image_width;
+            let row_start = row * image_width;
+            let segment_end = span_end.min(row_start + image_width);
+
+            if row >= y && row < y + height {
+                let from = (position - row_start).max(x);
+                let to = (segment_end - row_start).min(x + width);
+                if from < to {
+                    out.extend(core::iter::repeat_n(pixel, to - from));
+                }
+            }
+
+            position = segment_end;
+        }
+    }
+
+    let region_header = Header {
+        width: (width as u32).into(),
+        height: (height as u32).into(),
+        ..header
+    };
+
+    Some((region_header, out))
+}
+
+// the amount of chunks decoded per block, and the most bytes they can take up
+const BLOCK_CHUNKS: usize = 64;
+const BLOCK_BYTES: usize = BLOCK_CHUNKS * 5;
+
+// pixels are handled as little endian u32s in the fast path, so that deltas can be added to all channels at once.
+// this adds each byte separately, without carrying over into the next one.
+#[inline(always)]
+const fn add_channels(a: u32, b: u32) -> u32 {
+    ((a & 0x7f7f7f7f) + (b & 0x7f7f7f7f)) ^ ((a ^ b) & 0x80808080)
+}
+
+#[inline(always)]
+const fn pack_deltas(dr: i8, dg: i8, db: i8) -> u32 {
+    u32::from_le_bytes([dr as u8, dg as u8, db as u8, 0])
+}
+
+/// Channel deltas for DIFF chunks, by the lower 6 bits of the tag.
+const DIFF_DELTAS: [u32; 64] = {
+    let mut table = [0; 64];
+    let mut i = 0;
+    while i < 64 {
+        let (dr, dg, db) = ((i >> 4) & 3, (i >> 2) & 3, i & 3);
+        table[i] = pack_deltas(dr as i8 - 2, dg as i8 - 2, db as i8 - 2);
+        i += 1;
+    }
+    table
+};
+
+/// Channel deltas from the green difference of LUMA chunks, by the lower 6 bits of the tag.
+const LUMA_GREEN_DELTAS: [u32; 64] = {
+    let mut table = [0; 64];
+    let mut i = 0;
+    while i < 64 {
+        let dg = i as i8 - 32;
+        table[i] = pack_deltas(dg, dg, dg);
+        i += 1;
+    }
+    table
+};
+
+/// Channel deltas from the red & blue differences of LUMA chunks, by their second byte.
+const LUMA_RED_BLUE_DELTAS: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        table[i] = pack_deltas((i >> 4) as i8 - 8, 0, (i & 0x0f) as i8 - 8);
+        i += 1;
+    }
+    table
+};
+
+// state of the fast path decoder
+struct FastState {
+    previous: u32,
+    previously_seen: [u32; 64],
+    written: usize,
+}
+
+impl FastState {
+    #[inline(always)]
+    fn index_position(pixel: u32) -> usize {
+        let [r, g, b, a] = pixel.to_le_bytes();
+        (r.wrapping_mul(3)
+            .wrapping_add(g.wrapping_mul(5))
+            .wrapping_add(b.wrapping_mul(7))
+            .wrapping_add(a.wrapping_mul(11))
+            % 64) as usize
+    }
+
+    /// Decodes up to BLOCK_CHUNKS chunks into out, stopping early once it's full. Returns the amount of bytes consumed.
+    fn decode_block(&mut self, block: &[u8; BLOCK_BYTES], out: &mut [RgbaPixel]) -> usize {
+        let mut pos = 0;
+
+        for _ in 0..BLOCK_CHUNKS {
+            if self.written == out.len() {
+                break;
+            }
+
+            let start = pos;
+            // SAFETY: every chunk is at most 5 bytes long, so the at most BLOCK_CHUNKS chunks read here all fit in the block
+            let byte = |offset: usize| unsafe { *block.get_unchecked(start + offset) };
+            let tag = byte(0);
+
+            let pixel = match tag {
+                tags::RGB => {
+                    pos += 4;
+                    u32::from_le_bytes([byte(1), byte(2), byte(3), 0])
+                        | (self.previous & 0xff000000)
+                }
+                tags::RGBA => {
+                    pos += 5;
+                    u32::from_le_bytes([byte(1), byte(2), byte(3), byte(4)])
+                }
+                _ => match tag & tags::MASK_2 {
+                    tags::INDEX => {
+                        pos += 1;
+                        self.previously_seen[tag as usize]
+                    }
+                    tags::DIFF => {
+                        pos += 1;
+                        add_channels(
+                            self.previous,
+                            DIFF_DELTAS[(tag & tags::INVERSE_MASK_2) as usize],
+                        )
+                    }
+                    tags::LUMA => {
+                        let second_byte = byte(1);
+                        pos += 2;
+                        add_channels(
+                            add_channels(
+                                self.previous,
+                                LUMA_GREEN_DELTAS[(tag & tags::INVERSE_MASK_2) as usize],
+                            ),
+                            LUMA_RED_BLUE_DELTAS[second_byte as usize],
+                        )
+                    }
+                    _ => {
+                        pos += 1;
+
+                        // a run's extra pixels are written here; the last one is written below, like any other pixel
+                        let length = (tag & tags::INVERSE_MASK_2) as usize + 1;
+                        let end = (self.written + length - 1).min(out.len() - 1);
+                        out[self.written..end].fill(RgbaPixel::from(self.previous.to_le_bytes()));
+                        self.written = end;
+                        self.previous
+                    }
+                },
+            };
+
+            self.previously_seen[Self::index_position(pixel)] = pixel;
+            self.previous = pixel;
+            out[self.written] = RgbaPixel::from(pixel.to_le_bytes());
+            self.written += 1;
+        }
+
+        pos
+    }
+}
+
+/// The state of an [ImageDecoder], as saved by [ImageDecoder::snapshot].
+#[derive(AsBytes, FromBytes, Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+pub struct DecoderState {
+    /// The pixels left in the current run.
+    pub run: u8,
+    pub previous: RgbaPixel,
+    #[cfg_attr(feature = "serde", serde(with = "crate::helpers::serde_index"))]
+    pub previously_seen: [RgbaPixel; 64],
+}
+
 /// A QOI Decoder, built over an Iterator of QOI operation chunks.
 pub struct ImageDecoder<T: Iterator<Item = Chunk>> {
     inner: T,
@@ -126,7 +538,7 @@ impl<T: Iterator<Item = Chunk>> ImageDecoder<T> {
                 r: 0,
                 g: 0,
                 b: 0,
-                a: 255,
+                a: 0,
             }; 64],
             previous: RgbaPixel {
                 r: 0,
@@ -138,6 +550,25 @@ impl<T: Iterator<Item = Chunk>> ImageDecoder<T> {
         }
     }
 
+    /// Turns decoder into an iterator of pixels in the given [AlphaMode].
+    pub fn with_alpha(self, mode: AlphaMode) -> AlphaOutput<ImageDecoder<T>> {
+        AlphaOutput::new(self, mode)
+    }
+
+    /// Turns decoder into an iterator of grayscale bytes laid out as `layout`, failing on the first pixel that isn't gray.
+    pub fn into_gray(self, layout: GrayLayout) -> GrayOutput<ImageDecoder<T>> {
+        GrayOutput::new(self, layout)
+    }
+
+    /// Turns decoder into an iterator of pixels converted from the image's colorspace to another one.
+    pub fn in_colorspace(
+        self,
+        image: Colorspace,
+        target: Colorspace,
+    ) -> crate::colorspace::ColorspaceConverter<ImageDecoder<T>> {
+        crate::colorspace::ColorspaceConverter::new(self, image, target)
+    }
+
     /// Turns decoder into an iterator of RGBA bytes.
     pub fn into_rgba_bytes(self) -> PixelsToRgbaBytes<ImageDecoder<T>> {
         PixelsToRgbaBytes {
@@ -147,16 +578,75 @@ impl<T: Iterator<Item = Chunk>> ImageDecoder<T> {
     }
 }
 
-impl<T: Iterator<Item = Chunk>> Iterator for ImageDecoder<T> {
-    type Item = RgbaPixel;
+#[cfg(any(feature = "alloc", feature = "std"))]
+impl<'a> ImageDecoder<SliceReader<'a>> {
+    /// Records the decoder's state and position in the file, to later resume decoding from it.
+    pub fn checkpoint(&self, row: u32) -> crate::seek::Checkpoint {
+        crate::seek::Checkpoint {
+            row: row.into(),
+            offset: (self.inner.position() as u64).into(),
+            state: self.snapshot(),
+        }
+    }
 
-    fn next(&mut self) -> Option<RgbaPixel> {
+    /// Resumes decoding a QOI file from a checkpoint. Returns None if the checkpoint is out of the file's bounds or its state is invalid.
+    pub fn resume(
+        data: &'a [u8],
+        checkpoint: &crate::seek::Checkpoint,
+    ) -> Option<ImageDecoder<SliceReader<'a>>> {
+        let cursor = usize::try_from(checkpoint.offset.get()).ok()?;
+        if cursor < 14 || cursor > data.len() {
+            return None;
+        }
+
+        let mut decoder = ImageDecoder::new(SliceReader {
+            inner: data,
+            cursor,
+        });
+        decoder.restore(checkpoint.state)?;
+
+        Some(decoder)
+    }
+}
+
+impl<T: Iterator<Item = Chunk>> ImageDecoder<T> {
+    /// Saves the decoder's state. Restoring it into a decoder over the same chunks, starting from the next chunk this decoder would have read, resumes decoding where this one left off.
+    pub fn snapshot(&self) -> DecoderState {
+        DecoderState {
+            run: self.run,
+            previous: self.previous,
+            previously_seen: self.previously_seen,
+        }
+    }
+
+    /// Restores a state saved with [ImageDecoder::snapshot]. Returns None, leaving the decoder as it was, if the state's run is longer than a run chunk can hold.
+    pub fn restore(&mut self, state: DecoderState) -> Option<()> {
+        // the first pixel of a run is returned when its chunk is pushed, so at most 61 are pending
+        if state.run > 61 {
+            return None;
+        }
+
+        self.run = state.run;
+        self.previous = state.previous;
+        self.previously_seen = state.previously_seen;
+
+        Some(())
+    }
+
+    /// Returns the next pixel of a pending run, if there is one.
+    #[inline(always)]
+    pub fn next_run_pixel(&mut self) -> Option<RgbaPixel> {
         if self.run > 0 {
             self.run -= 1;
             return Some(self.previous);
         }
 
-        let next_pixel = match self.inner.next()? {
+        None
+    }
+
+    /// Decodes a chunk, returning its first pixel. Any remaining pixels of a run are returned by [ImageDecoder::next_run_pixel].
+    pub fn push_chunk(&mut self, chunk: Chunk) -> RgbaPixel {
+        let next_pixel = match chunk {
             Chunk::Rgb { r, g, b } => RgbaPixel {
                 r,
                 g,
@@ -186,7 +676,20 @@ impl<T: Iterator<Item = Chunk>> Iterator for ImageDecoder<T> {
         self.previous = next_pixel;
         self.previously_seen[next_pixel.index_position() as usize] = next_pixel;
 
-        Some(next_pixel)
+        next_pixel
+    }
+}
+
+impl<T: Iterator<Item = Chunk>> Iterator for ImageDecoder<T> {
+    type Item = RgbaPixel;
+
+    fn next(&mut self) -> Option<RgbaPixel> {
+        if let Some(pixel) = self.next_run_pixel() {
+            return Some(pixel);
+        }
+
+        let chunk = self.inner.next()?;
+        Some(self.push_chunk(chunk))
     }
 }
 
@@ -208,3 +711,343 @@ impl<T: Iterator<Item = RgbaPixel>> Iterator for PixelsToRgbaBytes<T> {
         self.buf.pop()
     }
 }
+
+/// A [std::io::Read] adapter over a decoder, yielding decoded RGBA or RGB bytes.
+///
+/// Reading stops once the header's `width * height` pixels have been read, and fails with [std::io::ErrorKind::UnexpectedEof] if the stream ends before that.
+#[cfg(feature = "std")]
+pub struct QoiDecodingReader<T: Iterator<Item = Chunk>> {
+    decoder: ImageDecoder<T>,
+    take_error: fn(&mut T) -> Option<std::io::Error>,
+    error: Option<std::io::Error>,
+    // pixels left in the image, according to its header
+    remaining: u64,
+    channels: usize,
+    // bytes of a pixel that didn't fit in the previous read
+    pending: ArrayVec<u8, 4>,
+    pending_pos: usize,
+}
+
+#[cfg(feature = "std")]
+impl<'a> QoiDecodingReader<SliceReader<'a>> {
+    /// Builds a reader over a QOI file in a slice, returning the QOI Header and the reader if it's a valid QOI file.
+    pub fn from_slice(data: &'a [u8]) -> Option<(Header, QoiDecodingReader<SliceReader<'a>>)> {
+        let (header, reader) = SliceReader::start(data)?;
+        Some((header, QoiDecodingReader::new(reader, |_| None, &header)))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read> QoiDecodingReader<StreamReader<R>> {
+    /// Builds a reader over a QOI file read from `inner`, returning the QOI Header and the reader if it's a valid QOI file.
+    pub fn from_reader(inner: R) -> std::io::Result<(Header, QoiDecodingReader<StreamReader<R>>)> {
+        let (header, reader) = StreamReader::start(inner)?;
+        Ok((
+            header,
+            QoiDecodingReader::new(reader, StreamReader::take_error, &header),
+        ))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: Iterator<Item = Chunk>> QoiDecodingReader<T> {
+    fn new(
+        inner: T,
+        take_error: fn(&mut T) -> Option<std::io::Error>,
+        header: &Header,
+    ) -> QoiDecodingReader<T> {
+        QoiDecodingReader {
+            decoder: ImageDecoder::new(inner),
+            take_error,
+            error: None,
+            remaining: header.width.get() as u64 * header.height.get() as u64,
+            channels: if header.channels == 3 { 3 } else { 4 },
+            pending: ArrayVec::new_const(),
+            pending_pos: 0,
+        }
+    }
+
+    /// Yield RGB bytes, dropping the alpha channel. By default, the header's channel count is used.
+    pub fn rgb(mut self) -> QoiDecodingReader<T> {
+        self.channels = 3;
+        self
+    }
+
+    /// Yield RGBA bytes. By default, the header's channel count is used.
+    pub fn rgba(mut self) -> QoiDecodingReader<T> {
+        self.channels = 4;
+        self
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: Iterator<Item = Chunk>> std::io::Read for QoiDecodingReader<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+
+        // first, hand out what's left of the last pixel
+        let pending = &self.pending[self.pending_pos..];
+        let mut written = pending.len().min(buf.len());
+        buf[..written].copy_from_slice(&pending[..written]);
+        self.pending_pos += written;
+
+        while written < buf.len() && self.remaining > 0 {
+            let pixel = match self.decoder.next() {
+                Some(pixel) => pixel,
+                // the stream ended before the image did
+                None => {
+                    self.error = Some((self.take_error)(&mut self.decoder.inner).unwrap_or_else(
+                        || {
+                            std::io::Error::new(
+                                std::io::ErrorKind::UnexpectedEof,
+                                "qoi stream ended early",
+                            )
+                        },
+                    ));
+                    self.remaining = 0;
+                    break;
+                }
+            };
+
+            self.remaining -= 1;
+
+            let bytes = [pixel.r, pixel.g, pixel.b, pixel.a];
+            let bytes = &bytes[..self.channels];
+            let space = buf.len() - written;
+
+            if space >= self.channels {
+                buf[written..written + self.channels].copy_from_slice(bytes);
+                written += self.channels;
+            } else {
+                buf[written..].copy_from_slice(&bytes[..space]);
+                written += space;
+                self.pending = bytes.iter().copied().collect();
+                self.pending_pos = space;
+            }
+        }
+
+        if written == 0 && !buf.is_empty() {
+            if let Some(e) = self.error.take() {
+                return Err(e);
+            }
+        }
+
+        Ok(written)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::encoder::Encoder;
+    use crate::test_utils::noisy_image;
+    use std::io::Read;
+
+    fn test_file(width: u32, height: u32) -> Vec<u8> {
+        let (header, pixels) = noisy_image(width, height);
+        Encoder::new(header).unwrap().image_to_vec(pixels).unwrap()
+    }
+
+    #[test]
+    fn decode_to_vec_matches_iterator() {
+        let file = test_file(67, 45);
+        let (_, reader) = SliceReader::start(&file).unwrap();
+        let expected: Vec<RgbaPixel> = reader.into_decoder().collect();
+        assert_eq!(expected.len(), 67 * 45);
+        assert_eq!(decode_to_vec(&file).unwrap().1, expected);
+    }
+
+    #[test]
+    fn decode_to_vec_rejects_short_streams() {
+        let header = Header::rgba(2, 1);
+        let pixels = [RgbaPixel::from([1, 2, 3, 4]), RgbaPixel::from([9, 8, 7, 6])];
+        let mut file = Encoder::new(header).unwrap().image_to_vec(pixels).unwrap();
+        assert_eq!(decode_to_vec(&file).unwrap().1, pixels);
+
+        // the header claims more pixels than the chunks hold: the end marker mustn't decode as pixels
+        file[4..8].copy_from_slice(&10u32.to_be_bytes());
+        assert!(decode_to_vec(&file).is_none());
+        assert_eq!(
+            SliceReader::start(&file).unwrap().1.into_decoder().count(),
+            2
+        );
+
+        // truncated in the middle of the chunks
+        let file = test_file(64, 64);
+        assert!(decode_to_vec(&file[..file.len() / 2]).is_none());
+    }
+
+    #[test]
+    fn decode_to_vec_rejects_huge_headers() {
+        for (width, height) in [(65535, 65535), (u32::MAX, 2), (u32::MAX, u32::MAX)] {
+            let mut file = tags::QOI_MAGIC.to_vec();
+            file.extend_from_slice(Header::rgba(width, height).as_bytes());
+            file.extend_from_slice(&tags::BYTESTREAM_END);
+
+            assert!(decode_to_vec(&file).is_none());
+            assert!(decode_region(&file, 0, 0, width, height).is_none());
+        }
+    }
+
+    #[test]
+    fn decoding_reader_reports_truncation() {
+        let file = test_file(64, 64);
+        let truncated = &file[..file.len() / 2];
+
+        let (_, mut reader) = QoiDecodingReader::from_slice(truncated).unwrap();
+        let error = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+
+        let (_, mut reader) = QoiDecodingReader::from_reader(truncated).unwrap();
+        let error = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn decoding_reader_stops_at_pixel_count() {
+        let file = test_file(64, 64);
+        let mut out = Vec::new();
+        QoiDecodingReader::from_slice(&file)
+            .unwrap()
+            .1
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out.len(), 64 * 64 * 4);
+
+        // a header claiming fewer pixels than the stream holds
+        let mut shorter = file.clone();
+        shorter[8..12].copy_from_slice(&32u32.to_be_bytes());
+        let mut short_out = Vec::new();
+        QoiDecodingReader::from_slice(&shorter)
+            .unwrap()
+            .1
+            .read_to_end(&mut short_out)
+            .unwrap();
+        assert_eq!(short_out, out[..64 * 32 * 4]);
+
+        short_out.clear();
+        QoiDecodingReader::from_reader(&shorter[..])
+            .unwrap()
+            .1
+            .read_to_end(&mut short_out)
+            .unwrap();
+        assert_eq!(short_out, out[..64 * 32 * 4]);
+    }
+
+    #[test]
+    fn restore_rejects_invalid_states() {
+        let file = test_file(10, 10);
+        let (_, reader) = SliceReader::start(&file).unwrap();
+        let mut decoder = reader.into_decoder();
+        decoder.by_ref().take(50).for_each(drop);
+        let checkpoint = decoder.checkpoint(5);
+
+        let mut resumed = ImageDecoder::resume(&file, &checkpoint).unwrap();
+        let state = resumed.snapshot();
+        assert!(resumed.restore(DecoderState { run: 61, ..state }).is_some());
+        assert!(resumed.restore(DecoderState { run: 62, ..state }).is_none());
+        assert_eq!(resumed.snapshot().run, 61);
+
+        let mut corrupt = checkpoint;
+        corrupt.state.run = 200;
+        assert!(ImageDecoder::resume(&file, &corrupt).is_none());
+    }
+
+    #[test]
+    fn index_starts_transparent_black() {
+        // the index starts zeroed in the encoder & decoder alike, so transparent black hits slot 0 straight away
+        let clear = RgbaPixel {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 0,
+        };
+        let header = Header::rgba(3, 1);
+        let pixels = [
+            clear,
+            RgbaPixel {
+                r: 9,
+                g: 9,
+                b: 9,
+                a: 0,
+            },
+            clear,
+        ];
+        let file = Encoder::new(header)
+            .unwrap()
+            .pixels_to_vec(&pixels)
+            .unwrap();
+        assert_eq!(file[14], tags::INDEX);
+
+        let (_, reader) = SliceReader::start(&file).unwrap();
+        assert_eq!(reader.into_decoder().collect::<Vec<_>>(), pixels);
+        assert_eq!(decode_to_vec(&file).unwrap().1, pixels);
+        assert_eq!(decode_region(&file, 2, 0, 1, 1).unwrap().1, [clear]);
+    }
+
+    fn crop(
+        pixels: &[RgbaPixel],
+        image_width: u32,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> Vec<RgbaPixel> {
+        pixels
+            .chunks(image_width as usize)
+            .skip(y as usize)
+            .take(height as usize)
+            .flat_map(|row| &row[x as usize..(x + width) as usize])
+            .copied()
+            .collect()
+    }
+
+    #[test]
+    fn decode_region_matches_cropping() {
+        // long runs crossing rows, with a few index hits
+        let header = Header::rgba(7, 40);
+        let runs: Vec<RgbaPixel> = (0..7 * 40)
+            .map(|i| {
+                let v = (i / 23 % 5) as u8 * 40;
+                RgbaPixel {
+                    r: v,
+                    g: 255 - v,
+                    b: v / 2,
+                    a: 255,
+                }
+            })
+            .collect();
+
+        for (header, pixels) in [noisy_image(67, 45), (header, runs)] {
+            let file = Encoder::new(header)
+                .unwrap()
+                .pixels_to_vec(&pixels)
+                .unwrap();
+            let (w, h) = (header.width.get(), header.height.get());
+
+            let regions = [
+                (0, 0, w, h),
+                (w - 1, h - 1, 1, 1),
+                (0, h - 1, w, 1),
+                (3, 0, 1, h),
+                (2, 5, w - 4, h / 2),
+            ];
+            for (x, y, width, height) in regions {
+                let (region_header, region) = decode_region(&file, x, y, width, height).unwrap();
+                assert_eq!(region_header.width.get(), width);
+                assert_eq!(region_header.height.get(), height);
+                assert!(
+                    region == crop(&pixels, w, x, y, width, height),
+                    "region {:?}",
+                    (x, y, width, height)
+                );
+            }
+
+            assert!(decode_region(&file, 1, 0, w, 1).is_none());
+            assert!(decode_region(&file, 0, h, 1, 1).is_none());
+            assert!(decode_region(&file, 0, 0, 0, 1).is_none());
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//! A decoder for the experimental 16-bit variant of QOI, turning a QOI16 file into [Rgba16Pixel]s. See [encoder16](crate::encoder16) for the format.
+
+use crate::*;
+
+/// Simple abstraction over a slice to help with reading QOI16 chunks.
+pub struct SliceReader16<'a> {
+    inner: &'a [u8],
+    cursor: usize,
+}
+
+impl<'a> SliceReader16<'a> {
+    /// Initializes the reader, returning the header and a Reader struct if it's a valid QOI16 file.
+    pub fn start(inner: &'a [u8]) -> Option<(Header, SliceReader16<'a>)> {
+        if *inner.get(0..4)? != tags::QOI16_MAGIC {
+            return None;
+        };
+
+        let header = Header::read_from(inner.get(4..14)?)?;
+
+        Some((header, SliceReader16 { cursor: 14, inner }))
+    }
+
+    /// Transforms reader into an image decoder.
+    pub fn into_decoder(self) -> ImageDecoder16<SliceReader16<'a>> {
+        ImageDecoder16::new(self)
+    }
+
+    fn peek_n<const N: usize>(&self) -> Option<&'a [u8; N]> {
+        if self.cursor + N > self.inner.len() {
+            return None;
+        }
+
+        Some(array_ref!(self.inner, self.cursor, N))
+    }
+
+    fn read_u8(&mut self) -> Option<u8> {
+        let old_cur = self.cursor;
+        self.cursor += 1;
+        if self.cursor > self.inner.len() {
+            return None;
+        }
+
+        Some(self.inner[old_cur])
+    }
+
+    fn read_n<const N: usize>(&mut self) -> Option<&'a [u8; N]> {
+        let old_cur = self.cursor;
+        self.cursor += N;
+        if self.cursor > self.inner.len() {
+            return None;
+        }
+
+        Some(array_ref!(self.inner, old_cur, N))
+    }
+}
+
+impl<'a> Iterator for SliceReader16<'a> {
+    type Item = Chunk16;
+
+    fn next(&mut self) -> Option<Chunk16> {
+        let tag = self.read_u8()?;
+
+        let u16_at = |bytes: &[u8], i: usize| u16::from_be_bytes([bytes[i], bytes[i + 1]]);
+
+        match tag {
+            tags::RGB => {
+                let bytes = self.read_n::<6>()?;
+
+                return Some(Chunk16::Rgb {
+                    r: u16_at(bytes, 0),
+                    g: u16_at(bytes, 2),
+                    b: u16_at(bytes, 4),
+                });
+            }
+            tags::RGBA => {
+                let bytes = self.read_n::<8>()?;
+
+                return Some(Chunk16::Rgba {
+                    r: u16_at(bytes, 0),
+                    g: u16_at(bytes, 2),
+                    b: u16_at(bytes, 4),
+                    a: u16_at(bytes, 6),
+                });
+            }
+            0 if self
+                .peek_n::<7>()
+                .filter(|b| b[..] == tags::BYTESTREAM_END[1..])
+                .is_some() =>
+            {
+                return None;
+            }
+            _ => (),
+        };
+
+        let masked_tag = tag & tags::MASK_2;
+        Some(match masked_tag {
+            tags::INDEX => Chunk16::Index { idx: tag },
+            tags::DIFF => {
+                let second_byte = self.read_u8()?;
+                Chunk16::Diff {
+                    dr: (tag & 0x0f) as i8 - 8,
+                    dg: (second_byte >> 4) as i8 - 8,
+                    db: (second_byte & 0x0f) as i8 - 8,
+                }
+            }
+            tags::LUMA => {
+                let [dg_low, dr_dg, db_dg] = *self.read_n::<3>()?;
+                Chunk16::Luma {
+                    dg: u16::from_be_bytes([tag & tags::INVERSE_MASK_2, dg_low]) as i16 - 8192,
+                    dr_dg: (dr_dg ^ 0x80) as i8,
+                    db_dg: (db_dg ^ 0x80) as i8,
+                }
+            }
+            tags::RUN => Chunk16::Run {
+                length: (tag & tags::INVERSE_MASK_2) + 1,
+            },
+            _ => unreachable!(),
+        })
+    }
+}
+
+/// A QOI16 Decoder, built over an Iterator of QOI16 operation chunks.
+pub struct ImageDecoder16<T: Iterator<Item = Chunk16>> {
+    inner: T,
+    previously_seen: [Rgba16Pixel; 64],
+    previous: Rgba16Pixel,
+    run: u8,
+}
+
+impl<T: Iterator<Item = Chunk16>> ImageDecoder16<T> {
+    /// Creates a QOI16 Decoder over an iterator of QOI16 operation chunks.
+    pub fn new(inner: T) -> ImageDecoder16<T> {
+        ImageDecoder16 {
+            inner,
+            previously_seen: [Rgba16Pixel {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: 0,
+            }; 64],
+            previous: Rgba16Pixel {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: u16::MAX,
+            },
+            run: 0,
+        }
+    }
+
+    /// Decodes a chunk, returning its first pixel.
+    fn push_chunk(&mut self, chunk: Chunk16) -> Rgba16Pixel {
+        let previous = self.previous;
+        let add = |channel: u16, delta: i16| channel.wrapping_add(delta as u16);
+
+        let next_pixel = match chunk {
+            Chunk16::Rgb { r, g, b } => Rgba16Pixel {
+                r,
+                g,
+                b,
+                a: previous.a,
+            },
+            Chunk16::Rgba { r, g, b, a } => Rgba16Pixel { r, g, b, a },
+            Chunk16::Index { idx } => self.previously_seen[idx as usize],
+            Chunk16::Luma { dg, dr_dg, db_dg } => Rgba16Pixel {
+                r: add(add(previous.r, dg), dr_dg as i16),
+                g: add(previous.g, dg),
+                b: add(add(previous.b, dg), db_dg as i16),
+                a: previous.a,
+            },
+            Chunk16::Diff { dr, dg, db } => Rgba16Pixel {
+                r: add(previous.r, dr as i16),
+                g: add(previous.g, dg as i16),
+                b: add(previous.b, db as i16),
+                a: previous.a,
+            },
+            Chunk16::Run { length } => {
+                self.run = length - 1;
+                previous
+            }
+        };
+
+        self.previous = next_pixel;
+        self.previously_seen[next_pixel.index_position() as usize] = next_pixel;
+
+        next_pixel
+    }
+}
+
+impl<T: Iterator<Item = Chunk16>> Iterator for ImageDecoder16<T> {
+    type Item = Rgba16Pixel;
+
+    fn next(&mut self) -> Option<Rgba16Pixel> {
+        if self.run > 0 {
+            self.run -= 1;
+            return Some(self.previous);
+        }
+
+        let chunk = self.inner.next()?;
+        Some(self.push_chunk(chunk))
+    }
+}
+
+/// Decodes a QOI16 file into a Vec of pixels. Returns None if the file is invalid or truncated.
+#[cfg(any(feature = "alloc", feature = "std"))]
+pub fn decode_to_vec(data: &[u8]) -> Option<(Header, Vec<Rgba16Pixel>)> {
+    let (header, reader) = SliceReader16::start(data)?;
+    if !header.is_valid() {
+        return None;
+    }
+
+    let count = header.pixel_count() as usize;
+    let mut pixels = crate::decoder::pixel_buffer(count as u64, data.len() - 14)?;
+    pixels.extend(reader.into_decoder().take(count));
+
+    if pixels.len() != count {
+        return None;
+    }
+
+    Some((header, pixels))
+}
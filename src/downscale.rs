@@ -0,0 +1,234 @@
+//! Downscaled decoding, for thumbnails and previews.
+//!
+//! [Downscaler] box-filters a stream of pixels down to 1/2, 1/4 or 1/8 of its size as it's decoded,
+//! only keeping one row of running sums around instead of the full size image.
+
+use crate::decoder::SliceReader;
+use crate::*;
+
+/// The factor an image is downscaled by.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Scale {
+    Half = 2,
+    Quarter = 4,
+    Eighth = 8,
+}
+
+impl Scale {
+    /// The width and height of a block of pixels averaged into a single one.
+    pub fn factor(self) -> u32 {
+        self as u32
+    }
+
+    /// Scales a dimension down, rounding up so that partial blocks at the edges are kept.
+    pub fn apply(self, dimension: u32) -> u32 {
+        dimension.div_ceil(self.factor())
+    }
+}
+
+/// An iterator that downscales the pixels of an image, averaging every block of factor x factor pixels into one.
+/// Blocks cut off by the right or bottom edge of the image are averaged over the pixels they hold.
+pub struct Downscaler<I: Iterator<Item = RgbaPixel>> {
+    inner: I,
+    header: Header,
+    scale: Scale,
+    // the sums of every channel for each block of the current band of rows
+    sums: Vec<[u32; 4]>,
+    // the rows of the source image read so far
+    row: u32,
+    // the amount of rows summed up in the current band
+    band_rows: u32,
+    // the next block of the current band to emit, once it's complete
+    emitting: Option<usize>,
+}
+
+impl<I: Iterator<Item = RgbaPixel>> Downscaler<I> {
+    /// Builds a downscaler over the pixels of an image described by `header`, such as an [ImageDecoder](crate::decoder::ImageDecoder).
+    pub fn new(inner: I, header: Header, scale: Scale) -> Downscaler<I> {
+        let mut sums = Vec::new();
+        sums.resize(scale.apply(header.width.get()) as usize, [0; 4]);
+
+        Downscaler {
+            inner,
+            header,
+            scale,
+            sums,
+            row: 0,
+            band_rows: 0,
+            emitting: None,
+        }
+    }
+
+    /// The header of the downscaled image.
+    pub fn header(&self) -> Header {
+        Header {
+            width: self.scale.apply(self.header.width.get()).into(),
+            height: self.scale.apply(self.header.height.get()).into(),
+            ..self.header
+        }
+    }
+
+    // sums up the next row of the source image into the current band
+    fn accumulate_row(&mut self) -> Option<()> {
+        let factor = self.scale.factor() as usize;
+        let width = self.header.width.get() as usize;
+
+        for x in 0..width {
+            let pixel = self.inner.next()?;
+            let sum = &mut self.sums[x / factor];
+            sum[0] += pixel.r as u32;
+            sum[1] += pixel.g as u32;
+            sum[2] += pixel.b as u32;
+            sum[3] += pixel.a as u32;
+        }
+
+        self.row += 1;
+        self.band_rows += 1;
+        Some(())
+    }
+
+    // averages a block of the current band, resetting its sums
+    fn average(&mut self, block: usize) -> RgbaPixel {
+        let factor = self.scale.factor();
+        let width = self.header.width.get();
+        let block_width = factor.min(width - block as u32 * factor);
+        let count = block_width * self.band_rows;
+
+        let sum = core::mem::take(&mut self.sums[block]);
+        let average = |channel: u32| ((channel + count / 2) / count) as u8;
+
+        RgbaPixel {
+            r: average(sum[0]),
+            g: average(sum[1]),
+            b: average(sum[2]),
+            a: average(sum[3]),
+        }
+    }
+}
+
+impl<I: Iterator<Item = RgbaPixel>> Iterator for Downscaler<I> {
+    type Item = RgbaPixel;
+
+    fn next(&mut self) -> Option<RgbaPixel> {
+        let block = match self.emitting {
+            Some(block) => block,
+            None => {
+                let height = self.header.height.get();
+                if self.row >= height || self.sums.is_empty() {
+                    return None;
+                }
+
+                self.band_rows = 0;
+                while self.band_rows < self.scale.factor() && self.row < height {
+                    self.accumulate_row()?;
+                }
+
+                0
+            }
+        };
+
+        let pixel = self.average(block);
+        self.emitting = if block + 1 < self.sums.len() {
+            Some(block + 1)
+        } else {
+            None
+        };
+
+        Some(pixel)
+    }
+}
+
+/// Decodes a QOI file at a reduced scale, returning the downscaled pixels along with their header. Returns None if the file is invalid or truncated.
+pub fn decode_downscaled(data: &[u8], scale: Scale) -> Option<(Header, Vec<RgbaPixel>)> {
+    let (header, reader) = SliceReader::start(data)?;
+    if !header.is_valid() {
+        return None;
+    }
+
+    let downscaler = Downscaler::new(reader.into_decoder(), header, scale);
+    let scaled_header = downscaler.header();
+
+    let pixels: Vec<RgbaPixel> = downscaler.collect();
+    if pixels.len() != scaled_header.pixel_count() as usize {
+        return None;
+    }
+
+    Some((scaled_header, pixels))
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::encoder::Encoder;
+    use crate::test_utils::noisy_image;
+
+    // averages every block of the image directly, rounding to the nearest level
+    fn box_filter(pixels: &[RgbaPixel], width: u32, height: u32, factor: u32) -> Vec<RgbaPixel> {
+        let mut out = Vec::new();
+        for by in (0..height).step_by(factor as usize) {
+            for bx in (0..width).step_by(factor as usize) {
+                let mut sum = [0u32; 4];
+                let mut count = 0;
+                for y in by..(by + factor).min(height) {
+                    for x in bx..(bx + factor).min(width) {
+                        let p = pixels[(y * width + x) as usize];
+                        sum[0] += p.r as u32;
+                        sum[1] += p.g as u32;
+                        sum[2] += p.b as u32;
+                        sum[3] += p.a as u32;
+                        count += 1;
+                    }
+                }
+
+                let average = |channel: u32| ((channel + count / 2) / count) as u8;
+                out.push(RgbaPixel {
+                    r: average(sum[0]),
+                    g: average(sum[1]),
+                    b: average(sum[2]),
+                    a: average(sum[3]),
+                });
+            }
+        }
+
+        out
+    }
+
+    #[test]
+    fn decode_downscaled_matches_box_filter() {
+        // sizes that are multiples of every factor, leave partial blocks, or are smaller than a block
+        for (width, height) in [(16, 8), (13, 9), (67, 45), (1, 1), (3, 17), (17, 2)] {
+            let (header, pixels) = noisy_image(width, height);
+            let file = Encoder::new(header)
+                .unwrap()
+                .pixels_to_vec(&pixels)
+                .unwrap();
+
+            for scale in [Scale::Half, Scale::Quarter, Scale::Eighth] {
+                let (scaled_header, scaled) = decode_downscaled(&file, scale).unwrap();
+                assert_eq!(scaled_header.width.get(), width.div_ceil(scale.factor()));
+                assert_eq!(scaled_header.height.get(), height.div_ceil(scale.factor()));
+                assert!(
+                    scaled == box_filter(&pixels, width, height, scale.factor()),
+                    "{}x{} at {:?}",
+                    width,
+                    height,
+                    scale
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn decode_downscaled_rejects_truncated_files() {
+        let (header, pixels) = noisy_image(67, 45);
+        let file = Encoder::new(header)
+            .unwrap()
+            .pixels_to_vec(&pixels)
+            .unwrap();
+
+        for scale in [Scale::Half, Scale::Quarter, Scale::Eighth] {
+            assert!(decode_downscaled(&file[..file.len() / 2], scale).is_none());
+            assert!(decode_downscaled(&file[..10], scale).is_none());
+        }
+    }
+}
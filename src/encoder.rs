@@ -1,21 +1,235 @@
 //! An encoder that turns RGBA bytes into a QOI file.
 
 use crate::*;
+use core::fmt;
+
+/// The size of the blocks chunks are gathered into before being written out.
+#[cfg(feature = "std")]
+pub(crate) const BLOCK_SIZE: usize = 4096;
+
+/// An error that occurred while encoding an image.
+#[derive(Debug)]
+pub enum EncodeError {
+    /// The image ended before `width * height` pixels were processed.
+    TooFewPixels { expected: u32, actual: u32 },
+    /// The image contains more than `width * height` pixels.
+    TooManyPixels { expected: u32 },
+    /// The header has a zero or overflowing size, an unknown channel count or an unknown colorspace.
+    InvalidHeader,
+    /// The output buffer is too small to hold the encoded image.
+    OutputBufferFull,
+    /// An animation frame is empty or doesn't fit within the canvas.
+    FrameOutOfBounds,
+    /// A restored state is past the end of the image, or its run is longer than a run chunk can hold.
+    InvalidState,
+    /// The bytes given end partway through a pixel.
+    IncompletePixel,
+    /// Writing the encoded image failed.
+    #[cfg(feature = "std")]
+    Io(std::io::Error),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::TooFewPixels { expected, actual } => write!(
+                f,
+                "image has too few pixels: expected {}, got {}",
+                expected, actual
+            ),
+            EncodeError::TooManyPixels { expected } => {
+                write!(f, "image has too many pixels: expected {}", expected)
+            }
+            EncodeError::InvalidHeader => f.write_str("invalid qoi header"),
+            EncodeError::OutputBufferFull => f.write_str("output buffer is full"),
+            EncodeError::FrameOutOfBounds => f.write_str("frame doesn't fit within the canvas"),
+            EncodeError::InvalidState => f.write_str("invalid encoder state"),
+            EncodeError::IncompletePixel => f.write_str("bytes end partway through a pixel"),
+            #[cfg(feature = "std")]
+            EncodeError::Io(e) => write!(f, "io error: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for EncodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EncodeError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for EncodeError {
+    fn from(e: std::io::Error) -> EncodeError {
+        EncodeError::Io(e)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<EncodeError> for std::io::Error {
+    fn from(e: EncodeError) -> std::io::Error {
+        match e {
+            EncodeError::Io(e) => e,
+            e => std::io::Error::new(std::io::ErrorKind::InvalidInput, e),
+        }
+    }
+}
+
+/// Checks that an image holds exactly the amount of pixels its header says, for functions taking the whole image at once.
+#[cfg(any(feature = "alloc", feature = "std"))]
+pub(crate) fn check_pixel_count(header: &Header, len: usize) -> Result<(), EncodeError> {
+    let expected = header.pixel_count();
+    if len < expected as usize {
+        return Err(EncodeError::TooFewPixels {
+            expected,
+            actual: len as u32,
+        });
+    }
+
+    if len > expected as usize {
+        return Err(EncodeError::TooManyPixels { expected });
+    }
+
+    Ok(())
+}
+
+// the previous pixel followed by a block of pixels, as analyzed by the simd module
+const WINDOW: usize = simd::LANES + 1;
+
+/// The state of an [Encoder], as saved by [Encoder::snapshot].
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EncoderState {
+    #[cfg_attr(feature = "serde", serde(with = "crate::helpers::serde_index"))]
+    pub previously_seen: [RgbaPixel; 64],
+    pub previous: RgbaPixel,
+    /// The length of the pending run.
+    pub run: u8,
+    /// The amount of pixels processed so far.
+    pub index: u32,
+}
+
+/// The amount of chunks of a kind an encoder emitted, and the bytes they took up.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct ChunkStats {
+    pub count: u64,
+    pub bytes: u64,
+}
+
+/// Statistics about what an encoder did, collected with [Encoder::with_stats].
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct EncodeStats {
+    pub rgb: ChunkStats,
+    pub rgba: ChunkStats,
+    pub index: ChunkStats,
+    pub diff: ChunkStats,
+    pub luma: ChunkStats,
+    pub run: ChunkStats,
+    /// The length of the longest Run chunk.
+    pub longest_run: u8,
+    /// The amount of pixels encoded.
+    pub pixels: u64,
+    /// The size of the image's raw pixels, width * height * channels.
+    pub raw_size: u64,
+    /// The sum of the squared per-channel differences between the pixels given and the ones encoded, 0 unless near-lossless.
+    pub squared_error: u64,
+}
+
+impl EncodeStats {
+    fn record(&mut self, chunk: &Chunk) {
+        let stats = match chunk {
+            Chunk::Rgb { .. } => &mut self.rgb,
+            Chunk::Rgba { .. } => &mut self.rgba,
+            Chunk::Index { .. } => &mut self.index,
+            Chunk::Diff { .. } => &mut self.diff,
+            Chunk::Luma { .. } => &mut self.luma,
+            Chunk::Run { length } => {
+                self.longest_run = self.longest_run.max(*length);
+                &mut self.run
+            }
+        };
+
+        stats.count += 1;
+        stats.bytes += chunk.encoded_len() as u64;
+    }
+
+    /// The total size of the QOI file, including its header and end marker.
+    pub fn output_size(&self) -> u64 {
+        let chunks = [
+            self.rgb, self.rgba, self.index, self.diff, self.luma, self.run,
+        ];
+        14 + chunks.iter().map(|chunk| chunk.bytes).sum::<u64>() + 8
+    }
+
+    /// The output size divided by the raw size. Images that compress poorly are close to or above 1.
+    pub fn size_ratio(&self) -> f64 {
+        self.output_size() as f64 / self.raw_size as f64
+    }
+
+    /// The share of chunks, excluding runs, that were index hits.
+    pub fn index_hit_ratio(&self) -> f64 {
+        let chunks =
+            self.rgb.count + self.rgba.count + self.index.count + self.diff.count + self.luma.count;
+        if chunks == 0 {
+            return 0.0;
+        }
+
+        self.index.count as f64 / chunks as f64
+    }
+
+    /// The peak signal-to-noise ratio of the whole image, in dB, once every pixel has been encoded. Infinite if it was encoded losslessly.
+    #[cfg(feature = "std")]
+    pub fn psnr(&self) -> f64 {
+        psnr(self.squared_error, self.raw_size)
+    }
+}
+
+#[cfg(feature = "std")]
+fn psnr(squared_error: u64, samples: u64) -> f64 {
+    if squared_error == 0 {
+        return f64::INFINITY;
+    }
+
+    10.0 * (255.0 * 255.0 * samples as f64 / squared_error as f64).log10()
+}
+
+/// What to replace the color of fully transparent pixels with. Their color is invisible, but random values defeat runs and index hits.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TransparentCleanup {
+    /// Transparent black.
+    Zero,
+    /// The color of the previous pixel, turning rows of transparent pixels into runs.
+    Previous,
+}
 
 /// A QOI encoder.
+#[derive(Clone)]
 pub struct Encoder {
     previously_seen: [RgbaPixel; 64],
     previous: RgbaPixel,
     run: u8,
     index: u32,
     length: u32,
+    // the largest per-channel error allowed in near-lossless mode, 0 if lossless
+    max_error: u8,
+    transparent_cleanup: Option<TransparentCleanup>,
+    stats: Option<EncodeStats>,
+    // the sum of the squared per-channel errors of the pixels encoded so far
+    squared_error: u64,
     pub header: Header,
 }
 
 impl Encoder {
-    /// Builds an encoder from a header.
-    pub fn new(header: Header) -> Encoder {
-        Encoder {
+    /// Builds an encoder from a header, failing if the header is invalid.
+    pub fn new(header: Header) -> Result<Encoder, EncodeError> {
+        if !header.is_valid() {
+            return Err(EncodeError::InvalidHeader);
+        }
+
+        Ok(Encoder {
             previously_seen: [RgbaPixel {
                 r: 0,
                 g: 0,
@@ -30,18 +244,489 @@ impl Encoder {
             },
             run: 0,
             index: 0,
-            length: header.width.get() * header.height.get(),
+            length: header.pixel_count(),
+            max_error: 0,
+            transparent_cleanup: None,
+            stats: None,
+            squared_error: 0,
             header,
+        })
+    }
+
+    /// Switches the encoder to near-lossless mode, allowing every channel of every pixel to be off by up to `max_error` levels.
+    ///
+    /// Pixels within the error budget are snapped to the previous pixel, to a pixel of the index, or into the range of a Diff or Luma chunk,
+    /// so the output is a standard QOI stream that decodes to the snapped pixels.
+    pub fn with_max_error(mut self, max_error: u8) -> Encoder {
+        self.max_error = max_error;
+        self
+    }
+
+    /// Normalizes the color of fully transparent pixels before encoding them. The visible image stays the same.
+    pub fn with_transparent_cleanup(mut self, cleanup: TransparentCleanup) -> Encoder {
+        self.transparent_cleanup = Some(cleanup);
+        self
+    }
+
+    /// Starts collecting statistics about the chunks emitted, read with [Encoder::stats].
+    pub fn with_stats(mut self) -> Encoder {
+        self.stats = Some(EncodeStats {
+            raw_size: self.length as u64 * self.header.channels as u64,
+            ..EncodeStats::default()
+        });
+        self
+    }
+
+    /// The statistics collected so far, if enabled with [Encoder::with_stats].
+    pub fn stats(&self) -> Option<&EncodeStats> {
+        self.stats.as_ref()
+    }
+
+    #[inline(always)]
+    fn record(&mut self, chunks: &[Chunk]) {
+        if let Some(stats) = &mut self.stats {
+            stats.pixels += 1;
+            for chunk in chunks {
+                stats.record(chunk);
+            }
         }
     }
 
-    /// Processes a pixel, emitting one to two chunks.
-    pub fn process_pixel(&mut self, pixel: RgbaPixel) -> ArrayVec<Chunk, 2> {
-        let mut output = ArrayVec::new_const();
+    /// The peak signal-to-noise ratio of the pixels encoded so far, in dB. Infinite if they were all encoded losslessly.
+    /// The functions that consume the encoder report it through [EncodeStats::psnr] instead.
+    #[cfg(feature = "std")]
+    pub fn psnr(&self) -> f64 {
+        psnr(
+            self.squared_error,
+            self.index as u64 * self.header.channels as u64,
+        )
+    }
+
+    /// Moves the encoder to a pixel index, as if the pixels before it had been processed.
+    #[cfg(any(feature = "alloc", feature = "std"))]
+    pub(crate) fn start_at(&mut self, index: u32) {
+        self.index = index;
+    }
+
+    /// Checks whether two encoders would emit the same chunks for the same pixels from now on, given the pixel that will next look up each index slot, if any.
+    ///
+    /// Their index slots may differ, as long as that next lookup is a hit for both encoders or a miss for both: either way, the slot then holds the same pixel in both.
+    #[cfg(any(feature = "alloc", feature = "std"))]
+    pub(crate) fn converges_with(
+        &self,
+        other: &Encoder,
+        next_lookup: impl Fn(usize) -> Option<RgbaPixel>,
+    ) -> bool {
+        self.previous == other.previous
+            && self.run == other.run
+            && self
+                .previously_seen
+                .iter()
+                .zip(&other.previously_seen)
+                .enumerate()
+                .all(|(slot, (a, b))| {
+                    a == b || next_lookup(slot).is_none_or(|pixel| (pixel == *a) == (pixel == *b))
+                })
+    }
+
+    /// Saves the encoder's state, to resume encoding later with [Encoder::restore].
+    pub fn snapshot(&self) -> EncoderState {
+        EncoderState {
+            previously_seen: self.previously_seen,
+            previous: self.previous,
+            run: self.run,
+            index: self.index,
+        }
+    }
+
+    /// Restores a state saved with [Encoder::snapshot], from an encoder for the same header. Fails if the state is past the end of the image, or its run is too long.
+    pub fn restore(&mut self, state: EncoderState) -> Result<(), EncodeError> {
+        if state.index > self.length || state.run >= 62 {
+            return Err(EncodeError::InvalidState);
+        }
+
+        self.previously_seen = state.previously_seen;
+        self.previous = state.previous;
+        self.run = state.run;
+        self.index = state.index;
+
+        Ok(())
+    }
+
+    /// Checks that every pixel of the image has been processed.
+    pub fn finish(&self) -> Result<(), EncodeError> {
+        if self.index < self.length {
+            return Err(EncodeError::TooFewPixels {
+                expected: self.length,
+                actual: self.index,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Processes a pixel, emitting zero to two chunks. Fails if the image already has all of its pixels.
+    pub fn process_pixel(&mut self, pixel: RgbaPixel) -> Result<ArrayVec<Chunk, 2>, EncodeError> {
+        if self.index == self.length {
+            return Err(EncodeError::TooManyPixels {
+                expected: self.length,
+            });
+        }
+
         self.index += 1;
 
+        let pixel = match self.transparent_cleanup {
+            Some(cleanup) if pixel.a == 0 => self.clean_transparent(cleanup),
+            _ => pixel,
+        };
+
+        let pixel = if self.max_error > 0 {
+            self.quantize(pixel)
+        } else {
+            pixel
+        };
+
+        let diff = [
+            pixel.r.wrapping_sub(self.previous.r),
+            pixel.g.wrapping_sub(self.previous.g),
+            pixel.b.wrapping_sub(self.previous.b),
+            pixel.a.wrapping_sub(self.previous.a),
+        ];
+
+        let chunks = self.encode_pixel(pixel, pixel == self.previous, pixel.index_position(), diff);
+        self.record(&chunks);
+
+        Ok(chunks)
+    }
+
+    /// Processes a slice of pixels, passing the emitted chunks to `emit`. Fails if the slice holds more pixels than the image has left.
+    ///
+    /// Runs, index positions and differences are computed for several pixels at once, using SIMD where available.
+    /// The chunks are the same as those emitted by [Encoder::process_pixel].
+    pub fn process_pixels(
+        &mut self,
+        pixels: &[RgbaPixel],
+        emit: impl FnMut(Chunk),
+    ) -> Result<(), EncodeError> {
+        // SAFETY: analyzer only returns functions supported by the current CPU
+        unsafe { self.process_pixels_with(pixels, simd::analyzer(), emit) }
+    }
+
+    /// [Encoder::process_pixels], analyzing blocks with `analyze`.
+    ///
+    /// # Safety
+    /// `analyze` must be supported by the current CPU.
+    unsafe fn process_pixels_with(
+        &mut self,
+        pixels: &[RgbaPixel],
+        analyze: simd::Analyzer,
+        mut emit: impl FnMut(Chunk),
+    ) -> Result<(), EncodeError> {
+        if pixels.len() > (self.length - self.index) as usize {
+            return Err(EncodeError::TooManyPixels {
+                expected: self.length,
+            });
+        }
+
+        // pixels are rewritten depending on the previous ones in near-lossless mode or with transparent cleanup, so those go through the scalar path
+        if self.max_error > 0 || self.transparent_cleanup.is_some() {
+            for &pixel in pixels {
+                for chunk in self.process_pixel(pixel)? {
+                    emit(chunk);
+                }
+            }
+
+            return Ok(());
+        }
+
+        let mut analysis = simd::Analysis::default();
+        let mut start = 0;
+
+        while start + simd::LANES <= pixels.len() {
+            // the window holds the previous pixel followed by the block being processed
+            let mut first_window = [self.previous; WINDOW];
+            let window = if start == 0 {
+                first_window[1..].copy_from_slice(&pixels[..simd::LANES]);
+                &first_window
+            } else {
+                array_ref!(pixels, start - 1, WINDOW)
+            };
+
+            // SAFETY: the caller checked that analyze is supported
+            unsafe { analyze(window, &mut analysis) };
+
+            // a block continuing the current run, that neither fills it up nor ends the image, can be skipped over entirely
+            if analysis.same == u8::MAX
+                && self.run as usize + simd::LANES < 62
+                && self.index as usize + simd::LANES < self.length as usize
+            {
+                self.run += simd::LANES as u8;
+                self.index += simd::LANES as u32;
+
+                if let Some(stats) = &mut self.stats {
+                    stats.pixels += simd::LANES as u64;
+                }
+            } else {
+                for (i, &pixel) in window[1..].iter().enumerate() {
+                    self.index += 1;
+                    let chunks = self.encode_pixel(
+                        pixel,
+                        analysis.same >> i & 1 == 1,
+                        analysis.hash[i],
+                        analysis.diff[i],
+                    );
+                    self.record(&chunks);
+
+                    for chunk in chunks {
+                        emit(chunk);
+                    }
+                }
+            }
+
+            start += simd::LANES;
+        }
+
+        for &pixel in &pixels[start..] {
+            for chunk in self.process_pixel(pixel)? {
+                emit(chunk);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn clean_transparent(&self, cleanup: TransparentCleanup) -> RgbaPixel {
+        match cleanup {
+            TransparentCleanup::Zero => RgbaPixel {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: 0,
+            },
+            TransparentCleanup::Previous => RgbaPixel {
+                a: 0,
+                ..self.previous
+            },
+        }
+    }
+
+    /// Picks the cheapest pixel to encode within the error budget of a pixel, keeping track of the error.
+    fn quantize(&mut self, pixel: RgbaPixel) -> RgbaPixel {
+        let snapped = self.snap(pixel);
+
+        let squared = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u64;
+        let mut error =
+            squared(pixel.r, snapped.r) + squared(pixel.g, snapped.g) + squared(pixel.b, snapped.b);
+        if self.header.channels == 4 {
+            error += squared(pixel.a, snapped.a);
+        }
+
+        self.squared_error += error;
+        if let Some(stats) = &mut self.stats {
+            stats.squared_error += error;
+        }
+
+        snapped
+    }
+
+    fn snap(&self, pixel: RgbaPixel) -> RgbaPixel {
+        let max_error = self.max_error as i16;
+        let previous = self.previous;
+        let distance = |other: &RgbaPixel| {
+            [
+                pixel.r as i16 - other.r as i16,
+                pixel.g as i16 - other.g as i16,
+                pixel.b as i16 - other.b as i16,
+                pixel.a as i16 - other.a as i16,
+            ]
+            .iter()
+            .map(|d| d.abs())
+            .max()
+            .unwrap_or(0)
+        };
+
+        // extend the run
+        if distance(&previous) <= max_error {
+            return previous;
+        }
+
+        // reuse the closest pixel of the index
+        if let Some(&seen) = self
+            .previously_seen
+            .iter()
+            .filter(|seen| distance(seen) <= max_error)
+            .min_by_key(|seen| distance(seen))
+        {
+            return seen;
+        }
+
+        // diff and luma chunks need the alpha to stay the same
+        if (pixel.a as i16 - previous.a as i16).abs() > max_error {
+            return pixel;
+        }
+
+        // moves a channel by delta from the previous pixel, if it stays in bounds and within the error budget
+        let channel = |value: u8, previous: u8, delta: i16| {
+            let moved = previous as i16 + delta;
+            ((0..=255).contains(&moved) && (moved - value as i16).abs() <= max_error)
+                .then_some(moved as u8)
+        };
+
+        let diff = |value: u8, previous: u8| {
+            channel(
+                value,
+                previous,
+                (value as i16 - previous as i16).clamp(-2, 1),
+            )
+        };
+
+        if let (Some(r), Some(g), Some(b)) = (
+            diff(pixel.r, previous.r),
+            diff(pixel.g, previous.g),
+            diff(pixel.b, previous.b),
+        ) {
+            return RgbaPixel {
+                r,
+                g,
+                b,
+                a: previous.a,
+            };
+        }
+
+        let dg = (pixel.g as i16 - previous.g as i16).clamp(-32, 31);
+        let luma = |value: u8, previous: u8| {
+            let d = value as i16 - previous as i16;
+            channel(value, previous, dg + (d - dg).clamp(-8, 7))
+        };
+
+        if let (Some(r), Some(g), Some(b)) = (
+            luma(pixel.r, previous.r),
+            channel(pixel.g, previous.g, dg),
+            luma(pixel.b, previous.b),
+        ) {
+            return RgbaPixel {
+                r,
+                g,
+                b,
+                a: previous.a,
+            };
+        }
+
+        // an Rgb chunk is still smaller than an Rgba one
+        RgbaPixel {
+            a: previous.a,
+            ..pixel
+        }
+    }
+
+    /// Processes grayscale bytes laid out as `layout`, passing the emitted chunks to `emit`.
+    /// Fails if the bytes hold more pixels than the image has left, or end partway through a pixel.
+    ///
+    /// Tuned for masks and other gray content: stretches of repeated pixels are measured straight from the bytes and emitted as runs at once,
+    /// and only one channel is hashed and diffed for other pixels. The chunks are the same as those emitted by [Encoder::process_pixel].
+    pub fn process_gray(
+        &mut self,
+        bytes: &[u8],
+        layout: GrayLayout,
+        mut emit: impl FnMut(Chunk),
+    ) -> Result<(), EncodeError> {
+        let stride = layout.stride();
+        if !bytes.len().is_multiple_of(stride) {
+            return Err(EncodeError::IncompletePixel);
+        }
+
+        let count = bytes.len() / stride;
+        if count > (self.length - self.index) as usize {
+            return Err(EncodeError::TooManyPixels {
+                expected: self.length,
+            });
+        }
+
+        // pixel rewriting options need the scalar path
+        if self.max_error > 0 || self.transparent_cleanup.is_some() {
+            for pixel in bytes.chunks_exact(stride) {
+                self.process_pixel(layout.pixel(pixel))?
+                    .into_iter()
+                    .for_each(&mut emit);
+            }
+
+            return Ok(());
+        }
+
+        let mut i = 0;
+        while i < count {
+            let pixel = layout.pixel(&bytes[i * stride..]);
+
+            if pixel == self.previous {
+                let repeated = bytes[i * stride..count * stride]
+                    .chunks_exact(stride)
+                    .take_while(|&next| layout.pixel(next) == pixel)
+                    .count();
+
+                i += repeated;
+                self.index += repeated as u32;
+
+                let mut run = self.run as usize + repeated;
+                let mut emit_run = |length: u8, stats: &mut Option<EncodeStats>| {
+                    let chunk = Chunk::Run { length };
+                    if let Some(stats) = stats {
+                        stats.record(&chunk);
+                    }
+                    emit(chunk);
+                };
+
+                while run >= 62 {
+                    emit_run(62, &mut self.stats);
+                    run -= 62;
+                }
+
+                if run > 0 && self.index == self.length {
+                    emit_run(run as u8, &mut self.stats);
+                    run = 0;
+                }
+
+                self.run = run as u8;
+                if let Some(stats) = &mut self.stats {
+                    stats.pixels += repeated as u64;
+                }
+
+                continue;
+            }
+
+            // a gray pixel hashes to (l * 15 + a * 11) % 64
+            let (l, a) = (pixel.g, pixel.a);
+            let index_pos = l.wrapping_mul(15).wrapping_add(a.wrapping_mul(11)) % 64;
+            let diff = [
+                l.wrapping_sub(self.previous.r),
+                l.wrapping_sub(self.previous.g),
+                l.wrapping_sub(self.previous.b),
+                a.wrapping_sub(self.previous.a),
+            ];
+
+            self.index += 1;
+            let chunks = self.encode_pixel(pixel, false, index_pos, diff);
+            self.record(&chunks);
+            chunks.into_iter().for_each(&mut emit);
+
+            i += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Encodes a pixel, given whether it's the same as the previous one, its index position & its per-channel wrapping difference from the previous pixel.
+    #[inline(always)]
+    fn encode_pixel(
+        &mut self,
+        pixel: RgbaPixel,
+        same: bool,
+        index_pos: u8,
+        diff: [u8; 4],
+    ) -> ArrayVec<Chunk, 2> {
+        let mut output = ArrayVec::new_const();
+
         // if pixel is the same as the last one, possibly emit a Run operation and return
-        if pixel == self.previous {
+        if same {
             self.run += 1;
 
             // if the run is 62, we've reached the maximum len QOI allows
@@ -68,7 +753,6 @@ impl Encoder {
         }
 
         // if pixel is in the previously seen array, return an Index operation and return
-        let index_pos = pixel.index_position();
         if self.previously_seen[index_pos as usize] == pixel {
             output.push(Chunk::Index { idx: index_pos });
             self.previous = pixel;
@@ -79,15 +763,15 @@ impl Encoder {
         self.previously_seen[index_pos as usize] = pixel;
 
         // if the alpha channel matches the previous pixel:
-        if pixel.a == self.previous.a {
+        if diff[3] == 0 {
             // pixel val diffs
-            let dr: i8 = pixel.r.wrapping_sub(self.previous.r) as i8;
-            let dg: i8 = pixel.g.wrapping_sub(self.previous.g) as i8;
-            let db: i8 = pixel.b.wrapping_sub(self.previous.b) as i8;
+            let dr = diff[0] as i8;
+            let dg = diff[1] as i8;
+            let db = diff[2] as i8;
 
             // diffs between the red and blue diffs and the green diff
-            let dr_dg = dr.wrapping_sub(dg) as i8;
-            let db_dg = db.wrapping_sub(dg) as i8;
+            let dr_dg = dr.wrapping_sub(dg);
+            let db_dg = db.wrapping_sub(dg);
 
             output.push(if in_diff_range(dr, dg, db) {
                 Chunk::Diff { dr, dg, db }
@@ -115,53 +799,957 @@ impl Encoder {
         output
     }
 
+    /// Encodes a slice of RgbaPixels into a Vec<u8> of QOI bytes, using [Encoder::process_pixels].
+    #[cfg(any(feature = "alloc", feature = "std"))]
+    pub fn pixels_to_vec(mut self, pixels: &[RgbaPixel]) -> Result<Vec<u8>, EncodeError> {
+        self.chunks_to_vec(|encoder, out| {
+            encoder.process_pixels(pixels, |chunk| chunk.write_to_vec(out))
+        })
+    }
+
+    /// Encodes grayscale bytes laid out as `layout` into a Vec<u8> of QOI bytes, using [Encoder::process_gray].
+    #[cfg(any(feature = "alloc", feature = "std"))]
+    pub fn gray_to_vec(mut self, bytes: &[u8], layout: GrayLayout) -> Result<Vec<u8>, EncodeError> {
+        self.chunks_to_vec(|encoder, out| {
+            encoder.process_gray(bytes, layout, |chunk| chunk.write_to_vec(out))
+        })
+    }
+
+    /// Encodes an iterator over RgbaPixels (or things that can be converted into RgbaPixels) into a byte slice, returning the amount of bytes written.
+    pub fn image_to_slice<T, I>(mut self, image: I, out: &mut [u8]) -> Result<usize, EncodeError>
+    where
+        T: Into<RgbaPixel>,
+        I: IntoIterator<Item = T>,
+    {
+        let mut cursor = 0;
+        let mut put = |bytes: &[u8]| -> Result<(), EncodeError> {
+            out.get_mut(cursor..cursor + bytes.len())
+                .ok_or(EncodeError::OutputBufferFull)?
+                .copy_from_slice(bytes);
+            cursor += bytes.len();
+            Ok(())
+        };
+
+        put(&tags::QOI_MAGIC)?;
+        put(self.header.as_bytes())?;
+
+        for pixel in image {
+            for chunk in self.process_pixel(pixel.into())? {
+                let mut bytes: ArrayVec<u8, 5> = ArrayVec::new_const();
+                chunk.write_to_arrayvec(&mut bytes);
+                put(&bytes)?;
+            }
+        }
+
+        self.finish()?;
+        put(&tags::BYTESTREAM_END)?;
+
+        Ok(cursor)
+    }
+
     /// Turns an iterator over RgbaPixels (or things that can be converted into RgbaPixels) into a Vec<u8> of QOI bytes.
     #[cfg(any(feature = "alloc", feature = "std"))]
-    pub fn image_to_vec<T, I>(mut self, image: I) -> Vec<u8>
+    pub fn image_to_vec<T, I>(mut self, image: I) -> Result<Vec<u8>, EncodeError>
+    where
+        T: Into<RgbaPixel>,
+        I: IntoIterator<Item = T>,
+    {
+        self.encode_to_vec(image)
+    }
+
+    /// Same as [Encoder::image_to_vec], also returning statistics about the encoding.
+    #[cfg(any(feature = "alloc", feature = "std"))]
+    pub fn image_to_vec_with_stats<T, I>(
+        self,
+        image: I,
+    ) -> Result<(Vec<u8>, EncodeStats), EncodeError>
     where
         T: Into<RgbaPixel>,
         I: IntoIterator<Item = T>,
     {
+        let mut encoder = self.with_stats();
+        let out = encoder.encode_to_vec(image)?;
+
+        Ok((out, encoder.stats.unwrap_or_default()))
+    }
+
+    #[cfg(any(feature = "alloc", feature = "std"))]
+    fn encode_to_vec<T, I>(&mut self, image: I) -> Result<Vec<u8>, EncodeError>
+    where
+        T: Into<RgbaPixel>,
+        I: IntoIterator<Item = T>,
+    {
+        self.chunks_to_vec(|encoder, out| {
+            for pixel in image {
+                for chunk in encoder.process_pixel(pixel.into())? {
+                    chunk.write_to_vec(out);
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Wraps the chunks written by `encode` in the magic, header & end marker of a QOI file, checking that the whole image was encoded.
+    #[cfg(any(feature = "alloc", feature = "std"))]
+    fn chunks_to_vec(
+        &mut self,
+        encode: impl FnOnce(&mut Encoder, &mut Vec<u8>) -> Result<(), EncodeError>,
+    ) -> Result<Vec<u8>, EncodeError> {
         // width * height * channels+1 + header size + bytestream end size
-        let mut out = Vec::with_capacity(
-            (self.header.width.get() * self.header.height.get() * (self.header.channels + 1) as u32
-                + 14
-                + 8) as usize,
-        );
+        let mut out =
+            Vec::with_capacity(self.length as usize * (self.header.channels as usize + 1) + 14 + 8);
 
         out.extend_from_slice(&tags::QOI_MAGIC);
         out.extend_from_slice(self.header.as_bytes());
 
-        for pixel in image {
-            for chunk in self.process_pixel(pixel.into()) {
-                chunk.write_to_vec(&mut out);
-            }
-        }
+        encode(self, &mut out)?;
 
+        self.finish()?;
         out.extend_from_slice(&tags::BYTESTREAM_END);
 
-        out
+        Ok(out)
     }
 
     /// Writes out an iterator over RgbaPixels (or things that can be converted into RgbaPixels) as QOI bytes into a [std::io::Write]
+    ///
+    /// Chunks are gathered into blocks before being written, so `out` doesn't need to be buffered.
     #[cfg(feature = "std")]
-    pub fn write_image<T, I, W>(mut self, image: I, out: &mut W) -> std::io::Result<()>
+    pub fn write_image<T, I, W>(self, image: I, out: &mut W) -> Result<(), EncodeError>
     where
         T: Into<RgbaPixel>,
         I: IntoIterator<Item = T>,
         W: std::io::Write,
     {
-        out.write_all(&tags::QOI_MAGIC)?;
-        out.write_all(self.header.as_bytes())?;
+        let mut writer = QoiWriter::new(self, out);
+        writer.write_pixels(image)?;
+        writer.finish()?;
 
-        for pixel in image {
-            for chunk in self.process_pixel(pixel.into()) {
-                chunk.write_into(out)?;
-            }
+        Ok(())
+    }
+
+    /// Same as [Encoder::write_image], returning statistics about the encoding.
+    #[cfg(feature = "std")]
+    pub fn write_image_with_stats<T, I, W>(
+        self,
+        image: I,
+        out: &mut W,
+    ) -> Result<EncodeStats, EncodeError>
+    where
+        T: Into<RgbaPixel>,
+        I: IntoIterator<Item = T>,
+        W: std::io::Write,
+    {
+        let mut writer = QoiWriter::new(self.with_stats(), out);
+        writer.write_pixels(image)?;
+        let stats = writer.encoder().stats().copied().unwrap_or_default();
+        writer.finish()?;
+
+        Ok(stats)
+    }
+}
+
+/// A QOI writer, encoding pixels incrementally into a [std::io::Write].
+///
+/// Chunks are gathered into an internal block before being written out, so the inner writer doesn't need to be buffered.
+/// The image is only complete once [QoiWriter::finish] is called.
+///
+/// Writing single pixels can be retried after an error. Writing a batch of pixels can't, as the pixels after the error have been encoded but not written:
+/// the writer is then poisoned, and every later call fails.
+#[cfg(feature = "std")]
+pub struct QoiWriter<W: std::io::Write> {
+    encoder: Encoder,
+    inner: W,
+    block: ArrayVec<u8, BLOCK_SIZE>,
+    // the kind of error that interrupted a batch of pixels, leaving the stream unusable
+    poisoned: Option<std::io::ErrorKind>,
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write> QoiWriter<W> {
+    /// Starts a QOI file in `inner`, using the encoder's header.
+    pub fn new(encoder: Encoder, inner: W) -> QoiWriter<W> {
+        let mut block = ArrayVec::new_const();
+        block.extend(tags::QOI_MAGIC);
+        block.extend(encoder.header.as_bytes().iter().copied());
+
+        QoiWriter {
+            encoder,
+            inner,
+            block,
+            poisoned: None,
+        }
+    }
+
+    /// The encoder used by this writer.
+    pub fn encoder(&self) -> &Encoder {
+        &self.encoder
+    }
+
+    /// Encodes a single pixel. If writing fails, the pixel isn't encoded, so it can be written again.
+    pub fn write_pixel(&mut self, pixel: RgbaPixel) -> Result<(), EncodeError> {
+        self.check_poisoned()?;
+
+        // make room for the most a pixel can emit, a run & an rgba chunk, before the encoder sees the pixel
+        if self.block.remaining_capacity() < 6 {
+            self.write_block()?;
         }
 
-        out.write_all(&tags::BYTESTREAM_END)?;
+        for chunk in self.encoder.process_pixel(pixel)? {
+            chunk.write_to_arrayvec(&mut self.block);
+        }
+
+        Ok(())
+    }
+
+    /// Encodes an iterator over RgbaPixels (or things that can be converted into RgbaPixels).
+    pub fn write_pixels<T, I>(&mut self, pixels: I) -> Result<(), EncodeError>
+    where
+        T: Into<RgbaPixel>,
+        I: IntoIterator<Item = T>,
+    {
+        for pixel in pixels {
+            self.write_pixel(pixel.into())?;
+        }
 
         Ok(())
     }
+
+    /// Encodes a slice of RgbaPixels, using [Encoder::process_pixels]. If writing fails, the writer is poisoned.
+    pub fn write_slice(&mut self, pixels: &[RgbaPixel]) -> Result<(), EncodeError> {
+        self.check_poisoned()?;
+
+        let QoiWriter {
+            encoder,
+            inner,
+            block,
+            ..
+        } = self;
+        let mut result = Ok(());
+
+        encoder.process_pixels(pixels, block_writer(inner, block, &mut result))?;
+
+        self.poison_on(result)
+    }
+
+    /// Encodes grayscale bytes laid out as `layout`, using [Encoder::process_gray]. If writing fails, the writer is poisoned.
+    pub fn write_gray(&mut self, bytes: &[u8], layout: GrayLayout) -> Result<(), EncodeError> {
+        self.check_poisoned()?;
+
+        let QoiWriter {
+            encoder,
+            inner,
+            block,
+            ..
+        } = self;
+        let mut result = Ok(());
+
+        encoder.process_gray(bytes, layout, block_writer(inner, block, &mut result))?;
+
+        self.poison_on(result)
+    }
+
+    /// Writes out the buffered chunks and flushes the inner writer.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.check_poisoned()?;
+        self.write_block()?;
+        self.inner.flush()
+    }
+
+    /// Checks that every pixel has been written, then writes the end of the stream and returns the inner writer.
+    pub fn finish(mut self) -> Result<W, EncodeError> {
+        self.check_poisoned()?;
+        self.encoder.finish()?;
+
+        if self
+            .block
+            .try_extend_from_slice(&tags::BYTESTREAM_END)
+            .is_err()
+        {
+            self.write_block()?;
+            self.block.extend(tags::BYTESTREAM_END);
+        }
+
+        self.flush()?;
+
+        Ok(self.inner)
+    }
+
+    fn check_poisoned(&self) -> std::io::Result<()> {
+        match self.poisoned {
+            Some(kind) => Err(std::io::Error::new(
+                kind,
+                "an earlier write failed partway through a batch of pixels",
+            )),
+            None => Ok(()),
+        }
+    }
+
+    // pixels past an error in a batch were encoded but dropped, so the stream can't be continued
+    fn poison_on(&mut self, result: std::io::Result<()>) -> Result<(), EncodeError> {
+        if let Err(e) = &result {
+            self.poisoned = Some(e.kind());
+        }
+
+        Ok(result?)
+    }
+
+    // writes out the block, keeping whatever wasn't written if writing fails, so that it isn't written twice on a retry
+    fn write_block(&mut self) -> std::io::Result<()> {
+        let mut written = 0;
+        let result = loop {
+            if written == self.block.len() {
+                break Ok(());
+            }
+
+            match self.inner.write(&self.block[written..]) {
+                Ok(0) => break Err(std::io::ErrorKind::WriteZero.into()),
+                Ok(n) => written += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+                Err(e) => break Err(e),
+            }
+        };
+
+        self.block.drain(..written);
+        result
+    }
+}
+
+// gathers chunks into `block`, writing it out to `inner` whenever it's full. the first error is kept in `result`,
+// & chunks are dropped from then on, as the encoder can't be stopped partway through a batch of pixels: the caller poisons the writer
+#[cfg(feature = "std")]
+fn block_writer<'a, W: std::io::Write>(
+    inner: &'a mut W,
+    block: &'a mut ArrayVec<u8, BLOCK_SIZE>,
+    result: &'a mut std::io::Result<()>,
+) -> impl FnMut(Chunk) + 'a {
+    move |chunk| {
+        if chunk.write_to_arrayvec(block).is_none() {
+            if result.is_ok() {
+                *result = inner.write_all(block);
+            }
+            block.clear();
+            chunk.write_to_arrayvec(block);
+        }
+    }
+}
+
+/// A [std::io::Write] that encodes the raw pixel bytes written into it as a QOI file.
+///
+/// Bytes are RGB or RGBA depending on the header's channel count, and may be written at arbitrary boundaries.
+/// The end of the stream is written by [QoiEncodingWriter::finish], or on drop if it wasn't called, ignoring any errors.
+#[cfg(feature = "std")]
+pub struct QoiEncodingWriter<W: std::io::Write> {
+    writer: Option<QoiWriter<W>>,
+    partial: ArrayVec<u8, 4>,
+    channels: usize,
+    // an error hit after part of a write went through, reported by the next write
+    error: Option<EncodeError>,
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write> QoiEncodingWriter<W> {
+    /// Starts a QOI file in `inner`, using the encoder's header.
+    pub fn new(encoder: Encoder, inner: W) -> QoiEncodingWriter<W> {
+        QoiEncodingWriter {
+            channels: encoder.header.channels as usize,
+            writer: Some(QoiWriter::new(encoder, inner)),
+            partial: ArrayVec::new_const(),
+            error: None,
+        }
+    }
+
+    /// Checks that every pixel has been written, then writes the end of the stream and returns the inner writer.
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.finish_inner()
+    }
+
+    fn finish_inner(&mut self) -> std::io::Result<W> {
+        let writer = self.writer.take().unwrap();
+        if !self.partial.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "image ends with an incomplete pixel",
+            ));
+        }
+
+        Ok(writer.finish()?)
+    }
+
+    #[inline(always)]
+    fn pixel_from_bytes(&self, bytes: &[u8]) -> RgbaPixel {
+        RgbaPixel {
+            r: bytes[0],
+            g: bytes[1],
+            b: bytes[2],
+            a: if self.channels == 4 { bytes[3] } else { 255 },
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write> std::io::Write for QoiEncodingWriter<W> {
+    /// Encodes the pixels in `buf`. If encoding fails after some of them went through, returns how many bytes were consumed and reports the error on the next call.
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let Some(error) = self.error.take() {
+            return Err(error.into());
+        }
+
+        let mut consumed = 0;
+
+        // complete a pixel left over from the previous write
+        if !self.partial.is_empty() {
+            let needed = (self.channels - self.partial.len()).min(buf.len());
+            if self.partial.len() + needed < self.channels {
+                self.partial.extend(buf.iter().copied());
+                return Ok(buf.len());
+            }
+
+            let mut bytes = self.partial.clone();
+            bytes.extend(buf[..needed].iter().copied());
+            let pixel = self.pixel_from_bytes(&bytes);
+            self.writer.as_mut().unwrap().write_pixel(pixel)?;
+
+            self.partial.clear();
+            consumed = needed;
+        }
+
+        let pixels = buf[consumed..].chunks_exact(self.channels);
+        let rest = pixels.remainder();
+        for bytes in pixels {
+            let pixel = self.pixel_from_bytes(bytes);
+            if let Err(error) = self.writer.as_mut().unwrap().write_pixel(pixel) {
+                if consumed == 0 {
+                    return Err(error.into());
+                }
+
+                self.error = Some(error);
+                return Ok(consumed);
+            }
+
+            consumed += self.channels;
+        }
+
+        self.partial.extend(rest.iter().copied());
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.as_mut().unwrap().flush()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write> Drop for QoiEncodingWriter<W> {
+    fn drop(&mut self) {
+        if self.writer.is_some() {
+            let _ = self.finish_inner();
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::test_utils::{gray_image, noisy_image, random_pixel, rng};
+
+    // inputs covering every chunk type, runs across and beyond blocks, and images ending in a run
+    fn differential_inputs() -> Vec<(&'static str, Vec<RgbaPixel>)> {
+        let mut rnd = rng(0x9e3779b97f4a7c15);
+
+        let random = (0..1003).map(|_| random_pixel(&mut rnd)).collect();
+
+        let palette: Vec<RgbaPixel> = (0..12).map(|_| random_pixel(&mut rnd)).collect();
+        let mut runs = Vec::new();
+        while runs.len() < 6000 {
+            let pixel = palette[rnd() as usize % palette.len()];
+            let length = [1, 2, 7, 8, 9, 61, 62, 63, 130][rnd() as usize % 9] + rnd() as usize % 3;
+            runs.extend(core::iter::repeat_n(pixel, length));
+        }
+
+        let mut alpha_toggling = Vec::new();
+        let mut pixel = random_pixel(&mut rnd);
+        while alpha_toggling.len() < 3000 {
+            let [dr, dg, db, step, ..] = rnd().to_le_bytes();
+            pixel.r = pixel.r.wrapping_add(dr % 5).wrapping_sub(2);
+            pixel.g = pixel.g.wrapping_add(dg % 5).wrapping_sub(2);
+            pixel.b = pixel.b.wrapping_add(db % 5).wrapping_sub(2);
+            if step % 4 == 0 {
+                pixel.a = if pixel.a == 255 { 100 } else { 255 };
+            }
+            alpha_toggling.push(pixel);
+        }
+
+        let mut smooth = Vec::new();
+        let mut pixel = random_pixel(&mut rnd);
+        while smooth.len() < 3000 {
+            let [dg, dr, db, repeat, ..] = rnd().to_le_bytes();
+            let dg = (dg % 80) as i8 - 40;
+            pixel.g = pixel.g.wrapping_add(dg as u8);
+            pixel.r = pixel
+                .r
+                .wrapping_add(dg as u8)
+                .wrapping_add(dr % 20)
+                .wrapping_sub(10);
+            pixel.b = pixel
+                .b
+                .wrapping_add(dg as u8)
+                .wrapping_add(db % 20)
+                .wrapping_sub(10);
+            smooth.extend(core::iter::repeat_n(pixel, 1 + (repeat % 4) as usize));
+        }
+
+        vec![
+            ("random", random),
+            ("runs", runs),
+            ("alpha toggling", alpha_toggling),
+            ("smooth", smooth),
+        ]
+    }
+
+    #[test]
+    fn process_pixels_matches_process_pixel() {
+        let mut rnd = rng(42);
+
+        for (input, pixels) in differential_inputs() {
+            let header = Header::rgba(pixels.len() as u32, 1);
+
+            let mut expected_encoder = Encoder::new(header).unwrap().with_stats();
+            let mut expected = Vec::new();
+            for &pixel in &pixels {
+                for chunk in expected_encoder.process_pixel(pixel).unwrap() {
+                    chunk.write_to_vec(&mut expected);
+                }
+            }
+
+            for (analyzer_name, analyzer) in simd::supported_analyzers() {
+                let mut encoder = Encoder::new(header).unwrap().with_stats();
+                let mut out = Vec::new();
+
+                // uneven slices, so blocks start at every offset & the first window of a slice gets used
+                let mut rest = &pixels[..];
+                while !rest.is_empty() {
+                    let (slice, next) = rest.split_at((1 + rnd() as usize % 40).min(rest.len()));
+                    // SAFETY: supported_analyzers only returns analyzers supported by the current CPU
+                    unsafe {
+                        encoder
+                            .process_pixels_with(slice, analyzer, |chunk| {
+                                chunk.write_to_vec(&mut out)
+                            })
+                            .unwrap()
+                    };
+                    rest = next;
+                }
+
+                encoder.finish().unwrap();
+                assert!(
+                    out == expected,
+                    "{} input, {} analyzer",
+                    input,
+                    analyzer_name
+                );
+                assert_eq!(
+                    format!("{:?}", encoder.stats()),
+                    format!("{:?}", expected_encoder.stats()),
+                    "{} input, {} analyzer",
+                    input,
+                    analyzer_name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn encoding_writer_retries_after_errors() {
+        use std::io::Write;
+
+        // fails every third write, up until the end of the image. with `partial`, the write before a failure only takes half its bytes
+        struct Flaky {
+            out: Vec<u8>,
+            writes: usize,
+            partial: bool,
+        }
+
+        impl Write for Flaky {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.writes += 1;
+                if self.writes < 30 {
+                    if self.writes.is_multiple_of(3) {
+                        return Err(std::io::ErrorKind::WouldBlock.into());
+                    }
+
+                    if self.partial && self.writes % 3 == 2 && buf.len() > 1 {
+                        self.out.extend_from_slice(&buf[..buf.len() / 2]);
+                        return Ok(buf.len() / 2);
+                    }
+                }
+
+                self.out.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let (header, pixels) = noisy_image(300, 200);
+        let bytes = pixels.as_bytes();
+        for partial in [false, true] {
+            let mut writer = QoiEncodingWriter::new(
+                Encoder::new(header).unwrap(),
+                Flaky {
+                    out: Vec::new(),
+                    writes: 0,
+                    partial,
+                },
+            );
+
+            // writes odd-sized slices, retrying the rest of a slice after an error
+            for mut slice in bytes.chunks(4093) {
+                while !slice.is_empty() {
+                    match writer.write(slice) {
+                        Ok(n) => slice = &slice[n..],
+                        Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::WouldBlock),
+                    }
+                }
+            }
+
+            assert!(
+                writer.finish().unwrap().out
+                    == Encoder::new(header)
+                        .unwrap()
+                        .pixels_to_vec(&pixels)
+                        .unwrap(),
+                "partial: {}",
+                partial
+            );
+        }
+    }
+
+    #[test]
+    fn restore_rejects_invalid_states() {
+        let (header, pixels) = noisy_image(10, 10);
+        let mut encoder = Encoder::new(header).unwrap();
+        encoder.process_pixels(&pixels[..50], |_| ()).unwrap();
+        let state = encoder.snapshot();
+
+        let mut restored = Encoder::new(header).unwrap();
+        assert!(restored.restore(state).is_ok());
+
+        let long_run = EncoderState { run: 62, ..state };
+        assert!(matches!(
+            restored.restore(long_run),
+            Err(EncodeError::InvalidState)
+        ));
+
+        let past_end = EncoderState {
+            index: 101,
+            ..state
+        };
+        assert!(matches!(
+            restored.restore(past_end),
+            Err(EncodeError::InvalidState)
+        ));
+    }
+
+    #[test]
+    fn stats_report_psnr() {
+        let (header, pixels) = noisy_image(67, 45);
+
+        let mut encoder = Encoder::new(header).unwrap().with_max_error(3);
+        let expected = encoder.encode_to_vec(pixels.iter().copied()).unwrap();
+        assert!(encoder.psnr().is_finite());
+
+        let near_lossless = Encoder::new(header).unwrap().with_max_error(3);
+        let (out, stats) = near_lossless
+            .image_to_vec_with_stats(pixels.iter().copied())
+            .unwrap();
+        assert_eq!(out, expected);
+        assert_eq!(stats.psnr(), encoder.psnr());
+
+        let mut written = Vec::new();
+        let near_lossless = Encoder::new(header).unwrap().with_max_error(3);
+        let stats = near_lossless
+            .write_image_with_stats(pixels.iter().copied(), &mut written)
+            .unwrap();
+        assert_eq!(written, expected);
+        assert_eq!(stats.psnr(), encoder.psnr());
+
+        let (_, stats) = Encoder::new(header)
+            .unwrap()
+            .image_to_vec_with_stats(pixels)
+            .unwrap();
+        assert_eq!(stats.psnr(), f64::INFINITY);
+    }
+
+    #[test]
+    fn transparent_cleanup_only_touches_invisible_colors() {
+        // stretches of fully transparent pixels with random colors among the visible ones
+        let (header, mut pixels) = noisy_image(67, 45);
+        let mut rnd = rng(7);
+        for (i, pixel) in pixels.iter_mut().enumerate() {
+            if (i / 50) % 3 == 0 {
+                *pixel = RgbaPixel {
+                    a: 0,
+                    ..random_pixel(&mut rnd)
+                };
+            }
+        }
+
+        let plain = Encoder::new(header)
+            .unwrap()
+            .pixels_to_vec(&pixels)
+            .unwrap();
+
+        for cleanup in [TransparentCleanup::Zero, TransparentCleanup::Previous] {
+            let cleaned = Encoder::new(header)
+                .unwrap()
+                .with_transparent_cleanup(cleanup)
+                .pixels_to_vec(&pixels)
+                .unwrap();
+            assert!(cleaned.len() < plain.len(), "{:?}", cleanup);
+
+            let (_, decoded) = crate::decoder::decode_to_vec(&cleaned).unwrap();
+            for (i, (&pixel, &decoded)) in pixels.iter().zip(&decoded).enumerate() {
+                if pixel.a == 0 {
+                    assert_eq!(decoded.a, 0, "{:?} pixel {}", cleanup, i);
+                    if cleanup == TransparentCleanup::Zero {
+                        assert_eq!(
+                            decoded,
+                            RgbaPixel {
+                                r: 0,
+                                g: 0,
+                                b: 0,
+                                a: 0
+                            }
+                        );
+                    }
+                } else {
+                    assert_eq!(decoded, pixel, "{:?} pixel {}", cleanup, i);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stats_count_known_chunks() {
+        let pixel = |r, g, b, a| RgbaPixel { r, g, b, a };
+        let mut pixels = vec![pixel(10, 20, 30, 255)]; // rgb
+        pixels.extend(core::iter::repeat_n(pixel(10, 20, 30, 255), 70)); // runs of 62 and 8
+        pixels.push(pixel(11, 21, 31, 255)); // diff
+        pixels.push(pixel(10, 20, 30, 255)); // index
+        pixels.push(pixel(10, 20, 30, 128)); // rgba
+        pixels.push(pixel(11, 21, 31, 255)); // index
+        pixels.push(pixel(31, 41, 51, 255)); // luma
+        pixels.extend(core::iter::repeat_n(pixel(31, 41, 51, 255), 3)); // a run ending the image
+        let header = Header::rgba(pixels.len() as u32, 1);
+
+        let chunks = |count, bytes| ChunkStats { count, bytes };
+        let expected = EncodeStats {
+            rgb: chunks(1, 4),
+            rgba: chunks(1, 5),
+            index: chunks(2, 2),
+            diff: chunks(1, 1),
+            luma: chunks(1, 2),
+            run: chunks(3, 3),
+            longest_run: 62,
+            pixels: 79,
+            raw_size: 79 * 4,
+            squared_error: 0,
+        };
+
+        let (out, stats) = Encoder::new(header)
+            .unwrap()
+            .image_to_vec_with_stats(pixels.iter().copied())
+            .unwrap();
+        assert_eq!(stats, expected);
+        assert_eq!(out.len(), 14 + 17 + 8);
+        assert_eq!(stats.output_size(), out.len() as u64);
+        assert_eq!(stats.index_hit_ratio(), 2.0 / 6.0);
+
+        // the batched path counts the same chunks
+        let mut encoder = Encoder::new(header).unwrap().with_stats();
+        let batched = encoder
+            .chunks_to_vec(|encoder, out| {
+                encoder.process_pixels(&pixels, |chunk| chunk.write_to_vec(out))
+            })
+            .unwrap();
+        assert_eq!(batched, out);
+        assert_eq!(encoder.stats(), Some(&expected));
+
+        assert_eq!(EncodeStats::default().index_hit_ratio(), 0.0);
+    }
+
+    #[test]
+    fn whole_image_functions_check_pixel_count() {
+        let (header, mut pixels) = noisy_image(12, 5);
+        pixels.push(pixels[0]);
+        let filter = crate::filter::Filter::Up;
+
+        assert!(matches!(
+            crate::filter::encode_filtered(header, &pixels, filter),
+            Err(EncodeError::TooManyPixels { expected: 60 })
+        ));
+        assert!(matches!(
+            crate::stripes::encode_stitched(header, &pixels, 2),
+            Err(EncodeError::TooManyPixels { expected: 60 })
+        ));
+        assert!(matches!(
+            crate::filter::encode_filtered(header, &pixels[..59], filter),
+            Err(EncodeError::TooFewPixels {
+                expected: 60,
+                actual: 59
+            })
+        ));
+    }
+
+    #[test]
+    fn process_gray_matches_process_pixels() {
+        for layout in [GrayLayout::L8, GrayLayout::La8] {
+            let bytes = gray_image(layout);
+            let pixels: Vec<_> = GrayBytesAdapter::new(&bytes, layout).collect();
+            let header = Header::rgba(pixels.len() as u32, 1);
+
+            let (expected, expected_stats) = Encoder::new(header)
+                .unwrap()
+                .image_to_vec_with_stats(pixels.iter().copied())
+                .unwrap();
+
+            let mut encoder = Encoder::new(header).unwrap().with_stats();
+            let gray = encoder
+                .chunks_to_vec(|encoder, out| {
+                    encoder.process_gray(&bytes, layout, |chunk| chunk.write_to_vec(out))
+                })
+                .unwrap();
+            assert!(gray == expected, "{:?}", layout);
+            assert_eq!(encoder.stats(), Some(&expected_stats));
+
+            // batches ending inside runs and pixels, with the image ending in a run
+            let stride = layout.stride();
+            let mut writer = QoiWriter::new(Encoder::new(header).unwrap(), Vec::new());
+            let mut rest = &bytes[..];
+            for length in [1, 2, 3, 61, 62, 63, 64, 125, 200].iter().cycle() {
+                if rest.is_empty() {
+                    break;
+                }
+
+                let (batch, next) = rest.split_at((length * stride).min(rest.len()));
+                if stride > 1 {
+                    assert!(matches!(
+                        writer.write_gray(&batch[..batch.len() - 1], layout),
+                        Err(EncodeError::IncompletePixel)
+                    ));
+                }
+                writer.write_gray(batch, layout).unwrap();
+                rest = next;
+            }
+            assert!(writer.finish().unwrap() == expected, "{:?}", layout);
+        }
+    }
+
+    #[test]
+    fn gray_rejects_incomplete_pixels() {
+        let header = Header::rgba(3, 1);
+        let bytes = [10, 255, 20, 255, 30];
+
+        assert!(matches!(
+            Encoder::new(header)
+                .unwrap()
+                .gray_to_vec(&bytes, GrayLayout::La8),
+            Err(EncodeError::IncompletePixel)
+        ));
+
+        let mut writer = QoiWriter::new(Encoder::new(header).unwrap(), Vec::new());
+        assert!(matches!(
+            writer.write_gray(&bytes, GrayLayout::La8),
+            Err(EncodeError::IncompletePixel)
+        ));
+
+        // nothing was encoded, so the complete pixels can still be written
+        writer.write_gray(&bytes[..4], GrayLayout::La8).unwrap();
+        writer.write_gray(&[30, 255], GrayLayout::La8).unwrap();
+        let gray = |l| RgbaPixel {
+            r: l,
+            g: l,
+            b: l,
+            a: 255,
+        };
+        assert_eq!(
+            writer.finish().unwrap(),
+            Encoder::new(header)
+                .unwrap()
+                .pixels_to_vec(&[gray(10), gray(20), gray(30)])
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn writer_is_poisoned_by_failed_batches() {
+        use std::io::Write;
+
+        // fails the first write only
+        struct FailOnce {
+            out: Vec<u8>,
+            failed: bool,
+        }
+
+        impl Write for FailOnce {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                if !self.failed {
+                    self.failed = true;
+                    return Err(std::io::ErrorKind::Other.into());
+                }
+
+                self.out.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let (header, pixels) = noisy_image(300, 200);
+        let (top, bottom) = pixels.split_at(pixels.len() / 2);
+        let gray: Vec<u8> = (0..300 * 100).map(|i| (i * 7919 % 251) as u8).collect();
+
+        for gray_batch in [false, true] {
+            let mut writer = QoiWriter::new(
+                Encoder::new(header).unwrap(),
+                FailOnce {
+                    out: Vec::new(),
+                    failed: false,
+                },
+            );
+
+            let first = if gray_batch {
+                writer.write_gray(&gray, GrayLayout::L8)
+            } else {
+                writer.write_slice(top)
+            };
+            assert!(
+                matches!(first, Err(EncodeError::Io(e)) if e.kind() == std::io::ErrorKind::Other)
+            );
+
+            assert!(matches!(
+                writer.write_slice(bottom),
+                Err(EncodeError::Io(_))
+            ));
+            assert!(matches!(
+                writer.write_gray(&gray, GrayLayout::L8),
+                Err(EncodeError::Io(_))
+            ));
+            assert!(matches!(
+                writer.write_pixel(bottom[0]),
+                Err(EncodeError::Io(_))
+            ));
+            assert_eq!(
+                writer.flush().unwrap_err().kind(),
+                std::io::ErrorKind::Other
+            );
+            assert!(matches!(writer.finish(), Err(EncodeError::Io(_))));
+        }
+    }
 }
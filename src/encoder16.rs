@@ -0,0 +1,323 @@
+//! An encoder for the experimental 16-bit variant of QOI, turning [Rgba16Pixel]s into a QOI16 file.
+//!
+//! QOI16 files start with the `qo16` magic followed by a standard [Header], and end with the standard end marker.
+//! The chunks are the same as QOI's, with wider Diff, Luma, Rgb and Rgba chunks: see [Chunk16].
+
+use crate::encoder::EncodeError;
+use crate::*;
+
+/// A QOI16 encoder.
+#[derive(Clone)]
+pub struct Encoder16 {
+    previously_seen: [Rgba16Pixel; 64],
+    previous: Rgba16Pixel,
+    run: u8,
+    index: u32,
+    length: u32,
+    pub header: Header,
+}
+
+impl Encoder16 {
+    /// Builds an encoder from a header, failing if the header is invalid.
+    pub fn new(header: Header) -> Result<Encoder16, EncodeError> {
+        if !header.is_valid() {
+            return Err(EncodeError::InvalidHeader);
+        }
+
+        Ok(Encoder16 {
+            previously_seen: [Rgba16Pixel {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: 0,
+            }; 64],
+            previous: Rgba16Pixel {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: u16::MAX,
+            },
+            run: 0,
+            index: 0,
+            length: header.pixel_count(),
+            header,
+        })
+    }
+
+    /// Checks that every pixel of the image has been processed.
+    pub fn finish(&self) -> Result<(), EncodeError> {
+        if self.index < self.length {
+            return Err(EncodeError::TooFewPixels {
+                expected: self.length,
+                actual: self.index,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Processes a pixel, emitting zero to two chunks. Fails if the image already has all of its pixels.
+    pub fn process_pixel(
+        &mut self,
+        pixel: Rgba16Pixel,
+    ) -> Result<ArrayVec<Chunk16, 2>, EncodeError> {
+        if self.index == self.length {
+            return Err(EncodeError::TooManyPixels {
+                expected: self.length,
+            });
+        }
+
+        self.index += 1;
+        let mut output = ArrayVec::new_const();
+
+        // if pixel is the same as the last one, extend the run
+        if pixel == self.previous {
+            self.run += 1;
+
+            if self.run == 62 || self.index == self.length {
+                output.push(Chunk16::Run {
+                    length: mem::take(&mut self.run),
+                });
+            }
+
+            return Ok(output);
+        }
+
+        if self.run > 0 {
+            output.push(Chunk16::Run {
+                length: mem::take(&mut self.run),
+            });
+        }
+
+        let index_pos = pixel.index_position();
+        if self.previously_seen[index_pos as usize] == pixel {
+            output.push(Chunk16::Index { idx: index_pos });
+            self.previous = pixel;
+            return Ok(output);
+        }
+
+        self.previously_seen[index_pos as usize] = pixel;
+
+        if pixel.a == self.previous.a {
+            let dr = pixel.r.wrapping_sub(self.previous.r) as i16;
+            let dg = pixel.g.wrapping_sub(self.previous.g) as i16;
+            let db = pixel.b.wrapping_sub(self.previous.b) as i16;
+
+            let dr_dg = dr.wrapping_sub(dg);
+            let db_dg = db.wrapping_sub(dg);
+
+            let in_diff_range = |d: i16| (-8..8).contains(&d);
+            let in_luma_range = |d: i16| (-128..128).contains(&d);
+
+            output.push(
+                if in_diff_range(dr) && in_diff_range(dg) && in_diff_range(db) {
+                    Chunk16::Diff {
+                        dr: dr as i8,
+                        dg: dg as i8,
+                        db: db as i8,
+                    }
+                } else if (-8192..8192).contains(&dg)
+                    && in_luma_range(dr_dg)
+                    && in_luma_range(db_dg)
+                {
+                    Chunk16::Luma {
+                        dg,
+                        dr_dg: dr_dg as i8,
+                        db_dg: db_dg as i8,
+                    }
+                } else {
+                    Chunk16::Rgb {
+                        r: pixel.r,
+                        g: pixel.g,
+                        b: pixel.b,
+                    }
+                },
+            );
+        } else {
+            output.push(Chunk16::Rgba {
+                r: pixel.r,
+                g: pixel.g,
+                b: pixel.b,
+                a: pixel.a,
+            });
+        }
+
+        self.previous = pixel;
+
+        Ok(output)
+    }
+
+    /// Turns an iterator over Rgba16Pixels (or things that can be converted into Rgba16Pixels) into a Vec<u8> of QOI16 bytes.
+    #[cfg(any(feature = "alloc", feature = "std"))]
+    pub fn image_to_vec<T, I>(mut self, image: I) -> Result<Vec<u8>, EncodeError>
+    where
+        T: Into<Rgba16Pixel>,
+        I: IntoIterator<Item = T>,
+    {
+        // width * height * (2 * channels + 1) + header size + bytestream end size
+        let mut out = Vec::with_capacity(
+            self.length as usize * (2 * self.header.channels as usize + 1) + 14 + 8,
+        );
+
+        out.extend_from_slice(&tags::QOI16_MAGIC);
+        out.extend_from_slice(self.header.as_bytes());
+
+        for pixel in image {
+            for chunk in self.process_pixel(pixel.into())? {
+                chunk.write_to_vec(&mut out);
+            }
+        }
+
+        self.finish()?;
+        out.extend_from_slice(&tags::BYTESTREAM_END);
+
+        Ok(out)
+    }
+
+    /// Writes out an iterator over Rgba16Pixels (or things that can be converted into Rgba16Pixels) as QOI16 bytes into a [std::io::Write]
+    ///
+    /// Chunks are gathered into blocks before being written, so `out` doesn't need to be buffered.
+    #[cfg(feature = "std")]
+    pub fn write_image<T, I, W>(mut self, image: I, out: &mut W) -> Result<(), EncodeError>
+    where
+        T: Into<Rgba16Pixel>,
+        I: IntoIterator<Item = T>,
+        W: std::io::Write,
+    {
+        let mut block: ArrayVec<u8, { encoder::BLOCK_SIZE }> = ArrayVec::new_const();
+        block.extend(tags::QOI16_MAGIC);
+        block.try_extend_from_slice(self.header.as_bytes()).unwrap();
+
+        for pixel in image {
+            for chunk in self.process_pixel(pixel.into())? {
+                if chunk.write_to_arrayvec(&mut block).is_none() {
+                    out.write_all(&block)?;
+                    block.clear();
+                    chunk.write_to_arrayvec(&mut block);
+                }
+            }
+        }
+
+        self.finish()?;
+
+        if block.try_extend_from_slice(&tags::BYTESTREAM_END).is_err() {
+            out.write_all(&block)?;
+            block.clear();
+            block.extend(tags::BYTESTREAM_END);
+        }
+
+        out.write_all(&block)?;
+        out.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::decoder16::{self, SliceReader16};
+
+    fn kind(chunk: &Chunk16) -> &'static str {
+        match chunk {
+            Chunk16::Rgb { .. } => "rgb",
+            Chunk16::Rgba { .. } => "rgba",
+            Chunk16::Index { .. } => "index",
+            Chunk16::Diff { .. } => "diff",
+            Chunk16::Luma { .. } => "luma",
+            Chunk16::Run { .. } => "run",
+        }
+    }
+
+    // each step moves the previous pixel by [dr, dg, db, da], expecting the chunk it's encoded as
+    const STEPS: &[([i16; 4], &str)] = &[
+        ([1000, 20000, 30000, 0], "rgb"),
+        // Diff covers -8..=7 on every channel
+        ([7, 7, 7, 0], "diff"),
+        ([-8, -8, -8, 0], "diff"),
+        ([-8, 7, 0, 0], "diff"),
+        ([8, 0, 0, 0], "luma"),
+        ([0, -9, 0, 0], "luma"),
+        // Luma covers dg in -8192..=8191, with dr - dg & db - dg in -128..=127
+        ([8191, 8191, 8191, 0], "luma"),
+        ([-8192, -8192, -8192, 0], "luma"),
+        ([8192, 8192, 8192, 0], "rgb"),
+        ([-8193, -8193, -8193, 0], "rgb"),
+        ([127, 0, -128, 0], "luma"),
+        ([-128 + 100, 100, 127 + 100, 0], "luma"),
+        ([128, 0, 0, 0], "rgb"),
+        ([0, 0, -129, 0], "rgb"),
+        ([-129 + 50, 50, 50, 0], "rgb"),
+        // differences wrap around, so the ranges hold across 0 & 65535
+        ([0, -25536, 0, 0], "rgb"),
+        ([8005, 8000, 8005, 0], "luma"),
+        ([3, 3, 3, 0], "diff"),
+        // any alpha change takes an Rgba chunk, even a tiny one
+        ([0, 0, 0, -1], "rgba"),
+        ([1, 1, 1, 0], "diff"),
+        ([0, 0, 0, -30000], "rgba"),
+        ([-5000, -5000, -5000, 0], "luma"),
+        ([0, 0, 0, 30001], "rgba"),
+        ([0, 0, 0, 0], "run"),
+    ];
+
+    fn step(pixel: Rgba16Pixel, [dr, dg, db, da]: [i16; 4]) -> Rgba16Pixel {
+        Rgba16Pixel {
+            r: pixel.r.wrapping_add(dr as u16),
+            g: pixel.g.wrapping_add(dg as u16),
+            b: pixel.b.wrapping_add(db as u16),
+            a: pixel.a.wrapping_add(da as u16),
+        }
+    }
+
+    #[test]
+    fn chunk_ranges_round_trip() {
+        let mut previous = Rgba16Pixel {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: u16::MAX,
+        };
+        let pixels: Vec<Rgba16Pixel> = STEPS
+            .iter()
+            .map(|&(delta, _)| {
+                previous = step(previous, delta);
+                previous
+            })
+            .collect();
+
+        let header = Header::rgba(pixels.len() as u32, 1);
+        let qoi16 = Encoder16::new(header)
+            .unwrap()
+            .image_to_vec(pixels.iter().copied())
+            .unwrap();
+
+        let (_, reader) = SliceReader16::start(&qoi16).unwrap();
+        let kinds: Vec<&str> = reader.map(|chunk| kind(&chunk)).collect();
+        let expected: Vec<&str> = STEPS.iter().map(|&(_, kind)| kind).collect();
+        assert_eq!(kinds, expected);
+
+        let (decoded_header, decoded) = decoder16::decode_to_vec(&qoi16).unwrap();
+        assert_eq!(decoded_header.as_bytes(), header.as_bytes());
+        assert_eq!(decoded, pixels);
+    }
+
+    #[test]
+    fn decode_to_vec_rejects_huge_headers() {
+        let header = Header::rgba(2, 2);
+        let pixels = [Rgba16Pixel {
+            r: 1,
+            g: 2,
+            b: 3,
+            a: 4,
+        }; 4];
+        let mut qoi16 = Encoder16::new(header)
+            .unwrap()
+            .image_to_vec(pixels)
+            .unwrap();
+        assert!(decoder16::decode_to_vec(&qoi16).is_some());
+
+        qoi16[4..12].copy_from_slice(&[0, 0, 0xff, 0xff, 0, 0, 0xff, 0xff]);
+        assert!(decoder16::decode_to_vec(&qoi16).is_none());
+    }
+}
@@ -0,0 +1,304 @@
+//! Filtered QOI files, passing pixels through a reversible filter before encoding them.
+//!
+//! A filtered file is made of:
+//! - the `qoi+` magic, so that standard decoders reject it instead of decoding the filtered pixels
+//! - the [Filter], as a byte
+//! - a standard QOI file, holding the filtered pixels under the image's own header.
+//!
+//! On photographic content the Up filter can shrink the output, as the residuals from the row above are smoother than the pixels themselves,
+//! while YCoCg-R tends to grow it, since QOI's Luma chunks already expect correlated channels. How much either changes depends on the image; measure with the `filter_bench` example.
+
+use crate::decoder::decode_to_vec;
+use crate::encoder::{check_pixel_count, EncodeError, Encoder};
+use crate::*;
+
+/// Magic bytes for filtered QOI files.
+pub const FILTERED_MAGIC: [u8; 4] = [b'q', b'o', b'i', b'+'];
+
+/// A reversible filter, applied to every pixel before encoding.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Filter {
+    /// Subtracts the pixel above from every channel, wrapping around.
+    /// Alpha residuals are offset by 255, so an unchanged alpha stays opaque and RGB images remain valid RGB streams.
+    Up = 1,
+    /// Converts colors to YCoCg-R with wrapping lifting steps, storing Co, Y and Cg as red, green and blue.
+    YCoCgR = 2,
+}
+
+impl Filter {
+    /// Reads a filter from its byte, returning None if it's unknown.
+    pub fn from_u8(value: u8) -> Option<Filter> {
+        match value {
+            1 => Some(Filter::Up),
+            2 => Some(Filter::YCoCgR),
+            _ => None,
+        }
+    }
+}
+
+// the pixel above the first row, which makes filtering it a no-op
+const FIRST_ABOVE: RgbaPixel = RgbaPixel {
+    r: 0,
+    g: 0,
+    b: 0,
+    a: 255,
+};
+
+fn up_residual(pixel: RgbaPixel, above: RgbaPixel) -> RgbaPixel {
+    RgbaPixel {
+        r: pixel.r.wrapping_sub(above.r),
+        g: pixel.g.wrapping_sub(above.g),
+        b: pixel.b.wrapping_sub(above.b),
+        a: pixel.a.wrapping_sub(above.a).wrapping_add(255),
+    }
+}
+
+fn up_unfilter(residual: RgbaPixel, above: RgbaPixel) -> RgbaPixel {
+    RgbaPixel {
+        r: residual.r.wrapping_add(above.r),
+        g: residual.g.wrapping_add(above.g),
+        b: residual.b.wrapping_add(above.b),
+        a: residual.a.wrapping_add(above.a).wrapping_sub(255),
+    }
+}
+
+// halves a wrapped difference, rounding towards negative infinity
+fn half(value: u8) -> u8 {
+    ((value as i8) >> 1) as u8
+}
+
+fn to_ycocg_r(pixel: RgbaPixel) -> RgbaPixel {
+    let co = pixel.r.wrapping_sub(pixel.b);
+    let t = pixel.b.wrapping_add(half(co));
+    let cg = pixel.g.wrapping_sub(t);
+    let y = t.wrapping_add(half(cg));
+
+    RgbaPixel {
+        r: co,
+        g: y,
+        b: cg,
+        a: pixel.a,
+    }
+}
+
+fn from_ycocg_r(pixel: RgbaPixel) -> RgbaPixel {
+    let (co, y, cg) = (pixel.r, pixel.g, pixel.b);
+    let t = y.wrapping_sub(half(cg));
+    let g = cg.wrapping_add(t);
+    let b = t.wrapping_sub(half(co));
+    let r = b.wrapping_add(co);
+
+    RgbaPixel {
+        r,
+        g,
+        b,
+        a: pixel.a,
+    }
+}
+
+/// Encodes an image as a filtered QOI file.
+pub fn encode_filtered(
+    header: Header,
+    pixels: &[RgbaPixel],
+    filter: Filter,
+) -> Result<Vec<u8>, EncodeError> {
+    let mut encoder = Encoder::new(header)?;
+    check_pixel_count(&header, pixels.len())?;
+
+    let mut out = Vec::with_capacity(5 + pixels.len() * (header.channels as usize + 1) + 14 + 8);
+    out.extend_from_slice(&FILTERED_MAGIC);
+    out.push(filter as u8);
+    out.extend_from_slice(&tags::QOI_MAGIC);
+    out.extend_from_slice(header.as_bytes());
+
+    // rows are filtered one at a time, then go through the encoder's fast path
+    let width = header.width.get() as usize;
+    let mut row = Vec::with_capacity(width);
+    for (y, pixels_row) in pixels.chunks(width).enumerate() {
+        row.clear();
+
+        match filter {
+            Filter::Up if y > 0 => {
+                let above = &pixels[(y - 1) * width..y * width];
+                row.extend(
+                    pixels_row
+                        .iter()
+                        .zip(above)
+                        .map(|(&pixel, &above)| up_residual(pixel, above)),
+                );
+            }
+            Filter::Up => row.extend_from_slice(pixels_row),
+            Filter::YCoCgR => row.extend(pixels_row.iter().map(|&pixel| to_ycocg_r(pixel))),
+        }
+
+        encoder.process_pixels(&row, |chunk| chunk.write_to_vec(&mut out))?;
+    }
+
+    encoder.finish()?;
+    out.extend_from_slice(&tags::BYTESTREAM_END);
+
+    Ok(out)
+}
+
+/// An adapter undoing a filter over the pixels of an [ImageDecoder](crate::decoder::ImageDecoder), keeping the previous row around.
+pub struct Unfilter<I: Iterator<Item = RgbaPixel>> {
+    inner: I,
+    filter: Filter,
+    // the row above, starting out as FIRST_ABOVE as the first row isn't filtered
+    above: Vec<RgbaPixel>,
+    x: usize,
+}
+
+impl<I: Iterator<Item = RgbaPixel>> Unfilter<I> {
+    /// Builds an adapter over the filtered pixels of an image `width` pixels wide.
+    pub fn new(inner: I, filter: Filter, width: u32) -> Unfilter<I> {
+        let mut above = Vec::new();
+        if filter == Filter::Up {
+            above.resize(width as usize, FIRST_ABOVE);
+        }
+
+        Unfilter {
+            inner,
+            filter,
+            above,
+            x: 0,
+        }
+    }
+}
+
+impl<I: Iterator<Item = RgbaPixel>> Iterator for Unfilter<I> {
+    type Item = RgbaPixel;
+
+    fn next(&mut self) -> Option<RgbaPixel> {
+        let pixel = self.inner.next()?;
+
+        match self.filter {
+            Filter::Up if self.above.is_empty() => Some(pixel),
+            Filter::Up => {
+                let pixel = up_unfilter(pixel, self.above[self.x]);
+                self.above[self.x] = pixel;
+                self.x = (self.x + 1) % self.above.len();
+
+                Some(pixel)
+            }
+            Filter::YCoCgR => Some(from_ycocg_r(pixel)),
+        }
+    }
+}
+
+/// Decodes a filtered QOI file, returning its header, unfiltered pixels and filter. Returns None if the file is invalid.
+pub fn decode_filtered(data: &[u8]) -> Option<(Header, Vec<RgbaPixel>, Filter)> {
+    if *data.get(0..4)? != FILTERED_MAGIC {
+        return None;
+    }
+
+    let filter = Filter::from_u8(*data.get(4)?)?;
+    let (header, mut pixels) = decode_to_vec(&data[5..])?;
+    let width = header.width.get() as usize;
+
+    match filter {
+        Filter::Up => {
+            for i in width..pixels.len() {
+                pixels[i] = up_unfilter(pixels[i], pixels[i - width]);
+            }
+        }
+        Filter::YCoCgR => {
+            for pixel in pixels.iter_mut() {
+                *pixel = from_ycocg_r(*pixel);
+            }
+        }
+    }
+
+    Some((header, pixels, filter))
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::decoder::SliceReader;
+    use crate::test_utils::{noisy_image, random_pixel, rng};
+
+    fn images() -> Vec<(Header, Vec<RgbaPixel>)> {
+        let mut rnd = rng(11);
+        let random = Header::rgba(31, 17);
+        let random_pixels = (0..random.pixel_count())
+            .map(|_| random_pixel(&mut rnd))
+            .collect();
+
+        vec![
+            noisy_image(1, 1),
+            noisy_image(1, 20),
+            noisy_image(20, 1),
+            noisy_image(67, 45),
+            (random, random_pixels),
+        ]
+    }
+
+    #[test]
+    fn filtered_round_trip() {
+        for (header, pixels) in images() {
+            for filter in [Filter::Up, Filter::YCoCgR] {
+                let encoded = encode_filtered(header, &pixels, filter).unwrap();
+                let (decoded_header, decoded, decoded_filter) = decode_filtered(&encoded).unwrap();
+                assert_eq!(decoded_header.as_bytes(), header.as_bytes());
+                assert_eq!(decoded_filter, filter);
+                assert!(
+                    decoded == pixels,
+                    "{:?} {}x{}",
+                    filter,
+                    header.width,
+                    header.height
+                );
+
+                let (_, reader) = SliceReader::start(&encoded[5..]).unwrap();
+                let unfiltered: Vec<_> =
+                    Unfilter::new(reader.into_decoder(), filter, header.width.get()).collect();
+                assert!(
+                    unfiltered == pixels,
+                    "{:?} {}x{}",
+                    filter,
+                    header.width,
+                    header.height
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn ycocg_r_is_reversible() {
+        let mut rnd = rng(5);
+        for _ in 0..100_000 {
+            let pixel = random_pixel(&mut rnd);
+            assert_eq!(from_ycocg_r(to_ycocg_r(pixel)), pixel);
+        }
+    }
+
+    #[test]
+    fn up_keeps_opaque_images_opaque() {
+        let (_, pixels) = noisy_image(40, 30);
+        let header = Header::rgb(40, 30);
+        let pixels: Vec<_> = pixels
+            .into_iter()
+            .map(|pixel| RgbaPixel { a: 255, ..pixel })
+            .collect();
+
+        let encoded = encode_filtered(header, &pixels, Filter::Up).unwrap();
+        let (_, residuals) = crate::decoder::decode_to_vec(&encoded[5..]).unwrap();
+        assert!(residuals.iter().all(|pixel| pixel.a == 255));
+        assert!(decode_filtered(&encoded).unwrap().1 == pixels);
+    }
+
+    #[test]
+    fn decode_filtered_rejects_unknown_files() {
+        let (header, pixels) = noisy_image(4, 4);
+        let mut encoded = encode_filtered(header, &pixels, Filter::Up).unwrap();
+        assert!(decode_filtered(&encoded[..4]).is_none());
+        assert!(decode_filtered(&encoded[..24]).is_none());
+
+        encoded[4] = 3;
+        assert!(decode_filtered(&encoded).is_none());
+        encoded[4] = 1;
+        encoded[0] = b'x';
+        assert!(decode_filtered(&encoded).is_none());
+    }
+}
@@ -21,13 +21,138 @@ impl<'a> Iterator for RgbaBytesAdapater<'a> {
 }
 
 impl<'a> From<&'a [u8]> for RgbaBytesAdapater<'a> {
-    fn from(slice: &'a [u8]) -> RgbaBytesAdapater {
+    fn from(slice: &'a [u8]) -> RgbaBytesAdapater<'a> {
         RgbaBytesAdapater {
             inner: slice.chunks_exact(4),
         }
     }
 }
 
+/// How the color channels of pixels relate to their alpha. QOI stores straight alpha.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AlphaMode {
+    /// The color channels are stored independently of alpha.
+    Straight,
+    /// The color channels are already multiplied by alpha.
+    Premultiplied,
+}
+
+impl AlphaMode {
+    /// Converts a pixel in this mode to straight alpha.
+    pub fn to_straight(self, pixel: RgbaPixel) -> RgbaPixel {
+        match self {
+            AlphaMode::Straight => pixel,
+            AlphaMode::Premultiplied => pixel.unpremultiply(),
+        }
+    }
+
+    /// Converts a straight alpha pixel to this mode.
+    pub fn from_straight(self, pixel: RgbaPixel) -> RgbaPixel {
+        match self {
+            AlphaMode::Straight => pixel,
+            AlphaMode::Premultiplied => pixel.premultiply(),
+        }
+    }
+}
+
+/// An adapter that converts pixels in an [AlphaMode] to straight alpha, to feed them to an encoder.
+pub struct AlphaInput<I> {
+    inner: I,
+    mode: AlphaMode,
+}
+
+impl<I: Iterator> AlphaInput<I>
+where
+    I::Item: Into<RgbaPixel>,
+{
+    pub fn new(inner: impl IntoIterator<IntoIter = I>, mode: AlphaMode) -> AlphaInput<I> {
+        AlphaInput {
+            inner: inner.into_iter(),
+            mode,
+        }
+    }
+}
+
+impl<I: Iterator> Iterator for AlphaInput<I>
+where
+    I::Item: Into<RgbaPixel>,
+{
+    type Item = RgbaPixel;
+
+    fn next(&mut self) -> Option<RgbaPixel> {
+        Some(self.mode.to_straight(self.inner.next()?.into()))
+    }
+}
+
+/// An adapter that converts straight alpha pixels, such as a decoder's, to an [AlphaMode].
+pub struct AlphaOutput<I: Iterator<Item = RgbaPixel>> {
+    inner: I,
+    mode: AlphaMode,
+}
+
+impl<I: Iterator<Item = RgbaPixel>> AlphaOutput<I> {
+    pub fn new(inner: I, mode: AlphaMode) -> AlphaOutput<I> {
+        AlphaOutput { inner, mode }
+    }
+}
+
+impl<I: Iterator<Item = RgbaPixel>> Iterator for AlphaOutput<I> {
+    type Item = RgbaPixel;
+
+    fn next(&mut self) -> Option<RgbaPixel> {
+        Some(self.mode.from_straight(self.inner.next()?))
+    }
+}
+
+// serde only implements its traits for arrays of up to 32 elements, so the pixel index is (de)serialized as a tuple by hand
+#[cfg(feature = "serde")]
+pub(crate) mod serde_index {
+    use crate::RgbaPixel;
+    use core::fmt;
+    use serde::de::{Error, SeqAccess, Visitor};
+    use serde::ser::SerializeTuple;
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(pixels: &[RgbaPixel; 64], s: S) -> Result<S::Ok, S::Error> {
+        let mut tuple = s.serialize_tuple(64)?;
+        for pixel in pixels {
+            tuple.serialize_element(pixel)?;
+        }
+        tuple.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<[RgbaPixel; 64], D::Error> {
+        struct IndexVisitor;
+
+        impl<'de> Visitor<'de> for IndexVisitor {
+            type Value = [RgbaPixel; 64];
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an array of 64 pixels")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut pixels = [RgbaPixel {
+                    r: 0,
+                    g: 0,
+                    b: 0,
+                    a: 0,
+                }; 64];
+
+                for (i, pixel) in pixels.iter_mut().enumerate() {
+                    *pixel = seq
+                        .next_element()?
+                        .ok_or_else(|| Error::invalid_length(i, &self))?;
+                }
+
+                Ok(pixels)
+            }
+        }
+
+        d.deserialize_tuple(64, IndexVisitor)
+    }
+}
+
 // adaptation of https://github.com/droundy/arrayref; license:
 /*
 Copyright (c) 2015 David Roundy <roundyd@physics.oregonstate.edu>
@@ -80,3 +205,323 @@ mod macros {
 }
 
 pub(crate) use macros::*;
+
+/// The layout of grayscale bytes: one luma byte per pixel, or a luma byte followed by an alpha byte.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GrayLayout {
+    /// A luma byte per pixel, every pixel being opaque.
+    L8,
+    /// A luma byte followed by an alpha byte per pixel.
+    La8,
+}
+
+impl GrayLayout {
+    /// The amount of bytes per pixel.
+    pub fn stride(self) -> usize {
+        match self {
+            GrayLayout::L8 => 1,
+            GrayLayout::La8 => 2,
+        }
+    }
+
+    /// Reads the pixel at the start of `bytes`, which must hold at least [GrayLayout::stride] bytes.
+    #[inline(always)]
+    pub fn pixel(self, bytes: &[u8]) -> RgbaPixel {
+        let l = bytes[0];
+        RgbaPixel {
+            r: l,
+            g: l,
+            b: l,
+            a: match self {
+                GrayLayout::L8 => 255,
+                GrayLayout::La8 => bytes[1],
+            },
+        }
+    }
+}
+
+/// A small adapter that transforms grayscale bytes into sRGBA pixels.
+pub struct GrayBytesAdapter<'a> {
+    inner: ChunksExact<'a, u8>,
+    layout: GrayLayout,
+}
+
+impl<'a> GrayBytesAdapter<'a> {
+    /// Builds an adapter over bytes laid out as `layout`.
+    pub fn new(slice: &'a [u8], layout: GrayLayout) -> GrayBytesAdapter<'a> {
+        GrayBytesAdapter {
+            inner: slice.chunks_exact(layout.stride()),
+            layout,
+        }
+    }
+
+    /// Builds an adapter over L8 bytes, with every pixel opaque.
+    pub fn l8(slice: &'a [u8]) -> GrayBytesAdapter<'a> {
+        GrayBytesAdapter::new(slice, GrayLayout::L8)
+    }
+
+    /// Builds an adapter over LA8 bytes.
+    pub fn la8(slice: &'a [u8]) -> GrayBytesAdapter<'a> {
+        GrayBytesAdapter::new(slice, GrayLayout::La8)
+    }
+}
+
+impl<'a> Iterator for GrayBytesAdapter<'a> {
+    type Item = RgbaPixel;
+
+    fn next(&mut self) -> Option<RgbaPixel> {
+        Some(self.layout.pixel(self.inner.next()?))
+    }
+}
+
+/// Error returned when a pixel can't be turned into grayscale: its channels differ, or it isn't opaque when converting to L8.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct NotGrayError {
+    /// The position of the pixel in the image.
+    pub position: usize,
+    pub pixel: RgbaPixel,
+}
+
+impl core::fmt::Display for NotGrayError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let RgbaPixel { r, g, b, a } = self.pixel;
+        write!(
+            f,
+            "pixel {} isn't gray: ({}, {}, {}, {})",
+            self.position, r, g, b, a
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for NotGrayError {}
+
+/// An adapter turning sRGBA pixels into grayscale bytes, failing on the first pixel that isn't gray.
+///
+/// Each item is a luma byte followed by an alpha byte for [GrayLayout::La8], or a lone luma byte for [GrayLayout::L8].
+pub struct GrayOutput<I: Iterator<Item = RgbaPixel>> {
+    inner: I,
+    layout: GrayLayout,
+    position: usize,
+    alpha: Option<u8>,
+    failed: bool,
+}
+
+impl<I: Iterator<Item = RgbaPixel>> GrayOutput<I> {
+    /// Builds an adapter writing out pixels as `layout`.
+    pub fn new(inner: I, layout: GrayLayout) -> GrayOutput<I> {
+        GrayOutput {
+            inner,
+            layout,
+            position: 0,
+            alpha: None,
+            failed: false,
+        }
+    }
+}
+
+impl<I: Iterator<Item = RgbaPixel>> Iterator for GrayOutput<I> {
+    type Item = Result<u8, NotGrayError>;
+
+    fn next(&mut self) -> Option<Result<u8, NotGrayError>> {
+        if let Some(alpha) = self.alpha.take() {
+            return Some(Ok(alpha));
+        }
+
+        if self.failed {
+            return None;
+        }
+
+        let pixel = self.inner.next()?;
+        let position = self.position;
+        self.position += 1;
+
+        let gray = pixel.r == pixel.g && pixel.g == pixel.b;
+        if !gray || (self.layout == GrayLayout::L8 && pixel.a != 255) {
+            self.failed = true;
+            return Some(Err(NotGrayError { position, pixel }));
+        }
+
+        if self.layout == GrayLayout::La8 {
+            self.alpha = Some(pixel.a);
+        }
+
+        Some(Ok(pixel.g))
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::decoder::SliceReader;
+    use crate::encoder::Encoder;
+    use crate::test_utils::gray_image;
+    use crate::Header;
+
+    fn pixel(r: u8, g: u8, b: u8, a: u8) -> RgbaPixel {
+        RgbaPixel { r, g, b, a }
+    }
+
+    #[test]
+    fn premultiply_rounds_to_nearest() {
+        for a in 0..=255u8 {
+            for c in 0..=255u8 {
+                let exact = c as f64 * a as f64 / 255.0;
+                let premultiplied = pixel(c, c, c, a).premultiply();
+                assert_eq!(premultiplied.r as f64, exact.round(), "c {} a {}", c, a);
+                assert_eq!(premultiplied.a, a);
+            }
+        }
+
+        assert_eq!(pixel(200, 100, 50, 0).premultiply(), pixel(0, 0, 0, 0));
+        assert_eq!(
+            pixel(200, 100, 50, 255).premultiply(),
+            pixel(200, 100, 50, 255)
+        );
+    }
+
+    #[test]
+    fn unpremultiply_rounds_and_clamps() {
+        for a in 1..=255u8 {
+            for c in 0..=a {
+                let exact = c as f64 * 255.0 / a as f64;
+                let straight = pixel(c, c, c, a).unpremultiply();
+                assert_eq!(straight.r as f64, exact.round(), "c {} a {}", c, a);
+
+                // premultiplying again gets back to where it started
+                assert_eq!(straight.premultiply(), pixel(c, c, c, a));
+            }
+        }
+
+        // transparent pixels have no color, & channels above alpha are invalid
+        assert_eq!(pixel(7, 8, 9, 0).unpremultiply(), pixel(0, 0, 0, 0));
+        assert_eq!(
+            pixel(200, 100, 50, 255).unpremultiply(),
+            pixel(200, 100, 50, 255)
+        );
+        assert_eq!(
+            pixel(200, 100, 50, 100).unpremultiply(),
+            pixel(255, 255, 128, 100)
+        );
+    }
+
+    #[test]
+    fn premultiplied_round_trip() {
+        // premultiplied pixels, with every channel at most alpha
+        let premultiplied: Vec<RgbaPixel> = (0..64 * 64u32)
+            .map(|i| {
+                let a = (i % 251) as u8;
+                let c = |k: u32| ((i * k) % (a as u32 + 1)) as u8;
+                pixel(c(7), c(13), c(31), a)
+            })
+            .collect();
+
+        let header = Header::rgba(64, 64);
+        let straight: Vec<RgbaPixel> =
+            AlphaInput::new(premultiplied.iter().copied(), AlphaMode::Premultiplied).collect();
+        let file = Encoder::new(header)
+            .unwrap()
+            .image_to_vec(AlphaInput::new(
+                premultiplied.iter().copied(),
+                AlphaMode::Premultiplied,
+            ))
+            .unwrap();
+        assert_eq!(
+            file,
+            Encoder::new(header)
+                .unwrap()
+                .pixels_to_vec(&straight)
+                .unwrap()
+        );
+
+        let (_, reader) = SliceReader::start(&file).unwrap();
+        let decoded: Vec<RgbaPixel> = reader
+            .into_decoder()
+            .with_alpha(AlphaMode::Premultiplied)
+            .collect();
+        assert_eq!(decoded, premultiplied);
+
+        // straight mode leaves pixels alone both ways
+        let (_, reader) = SliceReader::start(&file).unwrap();
+        let decoded: Vec<RgbaPixel> =
+            AlphaOutput::new(reader.into_decoder(), AlphaMode::Straight).collect();
+        assert_eq!(decoded, straight);
+        assert!(
+            AlphaInput::new(straight.iter().copied(), AlphaMode::Straight)
+                .eq(straight.iter().copied())
+        );
+    }
+
+    #[test]
+    fn gray_bytes_adapter_reads_layouts() {
+        let l8: Vec<_> = GrayBytesAdapter::l8(&[0, 128, 255]).collect();
+        assert_eq!(
+            l8,
+            [
+                pixel(0, 0, 0, 255),
+                pixel(128, 128, 128, 255),
+                pixel(255, 255, 255, 255)
+            ]
+        );
+
+        // a trailing incomplete pixel is left out
+        let la8: Vec<_> = GrayBytesAdapter::la8(&[10, 0, 20, 128, 30]).collect();
+        assert_eq!(la8, [pixel(10, 10, 10, 0), pixel(20, 20, 20, 128)]);
+    }
+
+    #[test]
+    fn gray_output_round_trip() {
+        for layout in [GrayLayout::L8, GrayLayout::La8] {
+            let bytes = gray_image(layout);
+            let pixels = (bytes.len() / layout.stride()) as u32;
+            let file = Encoder::new(Header::rgba(pixels, 1))
+                .unwrap()
+                .image_to_vec(GrayBytesAdapter::new(&bytes, layout))
+                .unwrap();
+
+            let (_, reader) = SliceReader::start(&file).unwrap();
+            let decoded: Result<Vec<u8>, _> = reader.into_decoder().into_gray(layout).collect();
+            assert_eq!(decoded.unwrap(), bytes, "{:?}", layout);
+        }
+    }
+
+    #[test]
+    fn gray_output_rejects_colors_and_translucent_l8() {
+        let gray_out = |pixels: &[RgbaPixel], layout| {
+            GrayOutput::new(pixels.iter().copied(), layout).collect::<Vec<_>>()
+        };
+
+        let colored = [
+            pixel(1, 1, 1, 255),
+            pixel(2, 3, 2, 255),
+            pixel(4, 4, 4, 255),
+        ];
+        let error = Err(NotGrayError {
+            position: 1,
+            pixel: colored[1],
+        });
+        assert_eq!(gray_out(&colored, GrayLayout::L8), [Ok(1), error]);
+        assert_eq!(gray_out(&colored, GrayLayout::La8), [Ok(1), Ok(255), error]);
+
+        // L8 can't hold alpha, while La8 can
+        let translucent = [
+            pixel(5, 5, 5, 255),
+            pixel(6, 6, 6, 254),
+            pixel(7, 7, 7, 255),
+        ];
+        assert_eq!(
+            gray_out(&translucent, GrayLayout::L8),
+            [
+                Ok(5),
+                Err(NotGrayError {
+                    position: 1,
+                    pixel: translucent[1]
+                })
+            ]
+        );
+        assert_eq!(
+            gray_out(&translucent, GrayLayout::La8),
+            [Ok(5), Ok(255), Ok(6), Ok(254), Ok(7), Ok(255)]
+        );
+    }
+}
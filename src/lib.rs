@@ -5,6 +5,12 @@ QOI decoder & encoder implementation. Heavily based on [the reference implementa
 Feature flags:
 - std: enables stdlib support, disables no_std. on by default.
 - alloc: enables use of Vec methods, using the alloc crate while keeping no_std. disabled by default.
+- tokio: enables async decoding & encoding over tokio's AsyncRead / AsyncWrite. implies std. disabled by default.
+- futures-io: enables async decoding & encoding over the futures AsyncRead / AsyncWrite. implies std. disabled by default.
+- rayon: encodes & decodes the stripes of striped images in parallel, and converts batches of files in parallel. implies std. disabled by default.
+- batch: enables batch conversion of image files to QOI using the image crate, and the teeny-qoi binary. implies std. disabled by default.
+- serde: derives Serialize & Deserialize for pixels and encoder / decoder state snapshots. disabled by default.
+- compress: enables wrapping QOI streams in LZ4 or zstd compression, using lz4_flex & ruzstd. implies std. disabled by default.
 */
 
 pub use arrayvec::ArrayVec;
@@ -15,16 +21,74 @@ use zerocopy::{AsBytes, BigEndian, FromBytes, U32};
 extern crate alloc;
 
 #[cfg(all(not(feature = "std"), feature = "alloc"))]
-use alloc::Vec;
+use alloc::vec::Vec;
 
 mod helpers;
 pub use helpers::*;
 
+mod simd;
+
+#[cfg(all(test, feature = "std"))]
+mod test_utils;
+
+pub mod colorspace;
 pub mod decoder;
+pub mod decoder16;
 pub mod encoder;
+pub mod encoder16;
+
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+pub mod async_io;
+
+#[cfg(any(feature = "alloc", feature = "std"))]
+pub mod stripes;
+
+#[cfg(any(feature = "alloc", feature = "std"))]
+pub mod seek;
+
+#[cfg(any(feature = "alloc", feature = "std"))]
+pub mod downscale;
+
+#[cfg(any(feature = "alloc", feature = "std"))]
+pub mod quantize;
+
+#[cfg(any(feature = "alloc", feature = "std"))]
+pub mod scan;
+
+#[cfg(any(feature = "alloc", feature = "std"))]
+pub mod filter;
+
+#[cfg(any(feature = "alloc", feature = "std"))]
+pub mod anim;
+
+#[cfg(feature = "batch")]
+pub mod batch;
+
+#[cfg(feature = "compress")]
+pub mod compress;
+
+/// The colorspace of a QOI image. It is purely informative: pixels are encoded as-is.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Colorspace {
+    /// sRGB color channels with linear alpha.
+    Srgb = 0,
+    /// All channels linear.
+    Linear = 1,
+}
+
+impl Colorspace {
+    /// Reads a colorspace from its header value, returning None if it's unknown.
+    pub fn from_u8(value: u8) -> Option<Colorspace> {
+        match value {
+            0 => Some(Colorspace::Srgb),
+            1 => Some(Colorspace::Linear),
+            _ => None,
+        }
+    }
+}
 
 /// A QOI header, containing width, height, channels (3 = RGB | 4 = RGBA) and colorspace (0 = sRGB + Linear Alpha; 1 = All Linear).
-#[derive(AsBytes, FromBytes, Debug)]
+#[derive(AsBytes, FromBytes, Clone, Copy, Debug)]
 #[repr(C)]
 pub struct Header {
     pub width: U32<BigEndian>,
@@ -53,6 +117,43 @@ impl Header {
             colorspace: 0,
         }
     }
+
+    /// Make a header for an RGB image with linear channels
+    pub fn rgb_linear(width: u32, height: u32) -> Header {
+        Header {
+            colorspace: Colorspace::Linear as u8,
+            ..Header::rgb(width, height)
+        }
+    }
+
+    /// Make a header for an RGBA image with linear channels
+    pub fn rgba_linear(width: u32, height: u32) -> Header {
+        Header {
+            colorspace: Colorspace::Linear as u8,
+            ..Header::rgba(width, height)
+        }
+    }
+
+    /// The header's colorspace, or None if its value is unknown.
+    pub fn colorspace(&self) -> Option<Colorspace> {
+        Colorspace::from_u8(self.colorspace)
+    }
+
+    /// Checks that the header describes an image this crate can encode: a non-zero size whose pixel count fits in a u32, 3 or 4 channels and a colorspace of 0 or 1.
+    pub fn is_valid(&self) -> bool {
+        let (width, height) = (self.width.get(), self.height.get());
+        width > 0
+            && height > 0
+            && width.checked_mul(height).is_some()
+            && (self.channels == 3 || self.channels == 4)
+            && self.colorspace <= 1
+    }
+
+    /// The number of pixels in the image, width * height.
+    #[inline(always)]
+    pub fn pixel_count(&self) -> u32 {
+        self.width.get() * self.height.get()
+    }
 }
 
 /// Binary tags & masks for QOI
@@ -70,6 +171,7 @@ pub mod tags {
     pub const LUMA_MASK: u8 = 0x0f; /* 00001111 */
 
     pub const QOI_MAGIC: [u8; 4] = [b'q', b'o', b'i', b'f'];
+    pub const QOI16_MAGIC: [u8; 4] = [b'q', b'o', b'1', b'6'];
     pub const BYTESTREAM_END: [u8; 8] = [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01];
 }
 
@@ -133,6 +235,16 @@ macro_rules! impl_chunk_as_bytes {
 }
 
 impl Chunk {
+    /// The amount of bytes the chunk takes up once encoded.
+    pub fn encoded_len(&self) -> usize {
+        match self {
+            Chunk::Rgb { .. } => 4,
+            Chunk::Rgba { .. } => 5,
+            Chunk::Luma { .. } => 2,
+            Chunk::Index { .. } | Chunk::Diff { .. } | Chunk::Run { .. } => 1,
+        }
+    }
+
     /// Writes out current chunk into an arrayvec. returns None on failure
     #[inline(always)]
     pub fn write_to_arrayvec<const CAP: usize>(&self, out: &mut ArrayVec<u8, CAP>) -> Option<()> {
@@ -165,6 +277,7 @@ impl Chunk {
 
 /// An sRGBA pixel.
 #[derive(AsBytes, FromBytes, Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 pub struct RgbaPixel {
     pub r: u8,
@@ -186,6 +299,50 @@ impl RgbaPixel {
         );
         (r * Wrapping(3u8) + g * Wrapping(5u8) + b * Wrapping(7u8) + a * Wrapping(11u8)).0 % 64
     }
+
+    /// Converts a straight alpha pixel to premultiplied alpha, rounding to the nearest value.
+    pub fn premultiply(self) -> RgbaPixel {
+        let a = self.a as u32;
+        let mul = |c: u8| {
+            let x = c as u32 * a + 128;
+            ((x + (x >> 8)) >> 8) as u8
+        };
+
+        RgbaPixel {
+            r: mul(self.r),
+            g: mul(self.g),
+            b: mul(self.b),
+            a: self.a,
+        }
+    }
+
+    /// Converts a premultiplied alpha pixel to straight alpha. Fully transparent pixels become transparent black, and channels above alpha are clamped.
+    pub fn unpremultiply(self) -> RgbaPixel {
+        let a = self.a as u32;
+        if a == 0 {
+            return RgbaPixel {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: 0,
+            };
+        }
+
+        let div = |c: u8| ((c as u32 * 255 + a / 2) / a).min(255) as u8;
+
+        RgbaPixel {
+            r: div(self.r),
+            g: div(self.g),
+            b: div(self.b),
+            a: self.a,
+        }
+    }
+
+    /// Reinterprets sRGBA bytes as a slice of pixels, without copying. Returns None if the length isn't a multiple of 4.
+    pub fn slice_from_bytes(bytes: &[u8]) -> Option<&[RgbaPixel]> {
+        zerocopy::LayoutVerified::<_, [RgbaPixel]>::new_slice(bytes)
+            .map(|pixels| pixels.into_slice())
+    }
 }
 
 impl From<[u8; 4]> for RgbaPixel {
@@ -211,3 +368,137 @@ impl From<(u8, u8, u8)> for RgbaPixel {
         RgbaPixel { r, g, b, a: 255 }
     }
 }
+
+/// An RGBA pixel with 16 bits per channel, for the experimental 16-bit variant of QOI.
+#[derive(AsBytes, FromBytes, Clone, Copy, PartialEq, Debug)]
+#[repr(C)]
+pub struct Rgba16Pixel {
+    pub r: u16,
+    pub g: u16,
+    pub b: u16,
+    pub a: u16,
+}
+
+impl Rgba16Pixel {
+    /// The pixel hash for Index operations, same as the 8-bit one: (r * 3 + g * 5 + b * 7 + a * 11) % 64
+    #[inline(always)]
+    pub fn index_position(&self) -> u8 {
+        (self
+            .r
+            .wrapping_mul(3)
+            .wrapping_add(self.g.wrapping_mul(5))
+            .wrapping_add(self.b.wrapping_mul(7))
+            .wrapping_add(self.a.wrapping_mul(11))
+            % 64) as u8
+    }
+}
+
+impl From<[u16; 4]> for Rgba16Pixel {
+    fn from([r, g, b, a]: [u16; 4]) -> Self {
+        Rgba16Pixel { r, g, b, a }
+    }
+}
+
+impl From<[u16; 3]> for Rgba16Pixel {
+    fn from([r, g, b]: [u16; 3]) -> Self {
+        Rgba16Pixel {
+            r,
+            g,
+            b,
+            a: u16::MAX,
+        }
+    }
+}
+
+impl From<RgbaPixel> for Rgba16Pixel {
+    /// Widens an 8-bit pixel, mapping 255 to 65535.
+    fn from(pixel: RgbaPixel) -> Self {
+        let widen = |c: u8| c as u16 * 257;
+        Rgba16Pixel {
+            r: widen(pixel.r),
+            g: widen(pixel.g),
+            b: widen(pixel.b),
+            a: widen(pixel.a),
+        }
+    }
+}
+
+/// An operation chunk of the 16-bit variant of QOI. Index and Run chunks are the same as 8-bit ones, while the others are widened.
+#[derive(Clone, Copy, Debug)]
+pub enum Chunk16 {
+    /// A new RGB pixel, as a tag followed by big endian channels. The alpha is copied from the previous pixel.
+    Rgb { r: u16, g: u16, b: u16 },
+    /// A new RGBA pixel, as a tag followed by big endian channels.
+    Rgba { r: u16, g: u16, b: u16, a: u16 },
+    /// An index into a rolling array of previously seen pixels
+    Index {
+        idx: u8, // bounded into 0..63
+    },
+    /// Adds a pixel with a small difference from the previously seen pixel, in 2 bytes
+    Diff {
+        dr: i8, // bounded into -8..7
+        dg: i8, // bounded into -8..7
+        db: i8, // bounded into -8..7
+    },
+    /// Adds a pixel with a larger difference from the previously seen pixel, in 4 bytes
+    Luma {
+        dg: i16,   // bounded into -8192..8191
+        dr_dg: i8, // full range
+        db_dg: i8, // full range
+    },
+    /// Adds an n-long run of the previous pixel
+    Run {
+        length: u8, // bounded into 1..62
+    },
+}
+
+impl Chunk16 {
+    /// Calls `f` with the chunk's bytes.
+    #[inline(always)]
+    pub fn with_bytes<R>(&self, f: impl FnOnce(&[u8]) -> R) -> R {
+        match *self {
+            Chunk16::Rgb { r, g, b } => {
+                let ([r0, r1], [g0, g1], [b0, b1]) =
+                    (r.to_be_bytes(), g.to_be_bytes(), b.to_be_bytes());
+                f(&[tags::RGB, r0, r1, g0, g1, b0, b1])
+            }
+            Chunk16::Rgba { r, g, b, a } => {
+                let ([r0, r1], [g0, g1], [b0, b1], [a0, a1]) = (
+                    r.to_be_bytes(),
+                    g.to_be_bytes(),
+                    b.to_be_bytes(),
+                    a.to_be_bytes(),
+                );
+                f(&[tags::RGBA, r0, r1, g0, g1, b0, b1, a0, a1])
+            }
+            Chunk16::Index { idx } => f(&[tags::INDEX | idx]),
+            Chunk16::Diff { dr, dg, db } => f(&[
+                tags::DIFF | (dr + 8) as u8,
+                ((dg + 8) as u8) << 4 | (db + 8) as u8,
+            ]),
+            Chunk16::Luma { dg, dr_dg, db_dg } => {
+                let [dg0, dg1] = ((dg + 8192) as u16).to_be_bytes();
+                f(&[
+                    tags::LUMA | dg0,
+                    dg1,
+                    (dr_dg as u8) ^ 0x80,
+                    (db_dg as u8) ^ 0x80,
+                ])
+            }
+            Chunk16::Run { length } => f(&[tags::RUN | (length - 1)]),
+        }
+    }
+
+    /// Writes out current chunk into an arrayvec. returns None on failure
+    #[inline(always)]
+    pub fn write_to_arrayvec<const CAP: usize>(&self, out: &mut ArrayVec<u8, CAP>) -> Option<()> {
+        self.with_bytes(|bytes| out.try_extend_from_slice(bytes).ok())
+    }
+
+    /// Writes out current chunk into a Vec.
+    #[cfg(any(feature = "alloc", feature = "std"))]
+    #[inline(always)]
+    pub fn write_to_vec(&self, out: &mut Vec<u8>) {
+        self.with_bytes(|bytes| out.extend_from_slice(bytes));
+    }
+}
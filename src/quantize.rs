@@ -0,0 +1,332 @@
+//! Colour quantisation, reducing images to a small palette before encoding them.
+//!
+//! Images with few colours compress very well, as most of their pixels turn into Index chunks.
+//! Palettes are built with median cut, and pixels can optionally be dithered with Floyd–Steinberg error diffusion.
+
+use crate::encoder::{EncodeError, Encoder};
+use crate::*;
+
+/// The largest palette [encode_to_size] tries.
+pub const MAX_COLORS: usize = 256;
+
+fn channels(pixel: &RgbaPixel) -> [u8; 4] {
+    [pixel.r, pixel.g, pixel.b, pixel.a]
+}
+
+/// Builds a palette of at most `colors` colours with median cut: the pixels are repeatedly split in two along the channel with the widest range, and each group is averaged into a colour.
+pub fn median_cut(pixels: &[RgbaPixel], colors: usize) -> Vec<RgbaPixel> {
+    let mut pixels = pixels.to_vec();
+    let mut boxes = Vec::with_capacity(colors);
+    if !pixels.is_empty() && colors > 0 {
+        boxes.push(0..pixels.len());
+    }
+
+    // the channel with the widest range in a box, and that range
+    let widest = |pixels: &[RgbaPixel]| {
+        (0..4)
+            .map(|channel| {
+                let values = pixels.iter().map(|pixel| channels(pixel)[channel]);
+                let range = values.clone().max().unwrap_or(0) - values.min().unwrap_or(0);
+                (channel, range)
+            })
+            .max_by_key(|&(_, range)| range)
+            .unwrap_or((0, 0))
+    };
+
+    while boxes.len() < colors {
+        let Some((i, channel)) = boxes
+            .iter()
+            .enumerate()
+            .map(|(i, range)| (i, widest(&pixels[range.clone()])))
+            .filter(|&(_, (_, range))| range > 0)
+            .max_by_key(|&(_, (_, range))| range)
+            .map(|(i, (channel, _))| (i, channel))
+        else {
+            // every box holds a single colour
+            break;
+        };
+
+        let range = boxes[i].clone();
+        pixels[range.clone()].sort_unstable_by_key(|pixel| channels(pixel)[channel]);
+
+        // split between two values closest to the median, so equal colours stay in one box and the palette has no duplicates
+        let sorted = &pixels[range.clone()];
+        let value = channels(&sorted[sorted.len() / 2])[channel];
+        let below = sorted.partition_point(|pixel| channels(pixel)[channel] < value);
+        let above = sorted.partition_point(|pixel| channels(pixel)[channel] <= value);
+        let split = if below == 0
+            || (above < sorted.len() && above - sorted.len() / 2 < sorted.len() / 2 - below)
+        {
+            above
+        } else {
+            below
+        };
+
+        let median = range.start + split;
+        boxes[i] = range.start..median;
+        boxes.push(median..range.end);
+    }
+
+    boxes
+        .into_iter()
+        .map(|range| {
+            let mut sum = [0u64; 4];
+            for pixel in &pixels[range.clone()] {
+                for (sum, value) in sum.iter_mut().zip(channels(pixel)) {
+                    *sum += value as u64;
+                }
+            }
+
+            let count = range.len() as u64;
+            let average = |sum: u64| ((sum + count / 2) / count) as u8;
+            RgbaPixel {
+                r: average(sum[0]),
+                g: average(sum[1]),
+                b: average(sum[2]),
+                a: average(sum[3]),
+            }
+        })
+        .collect()
+}
+
+fn nearest(palette: &[RgbaPixel], target: [i32; 4]) -> RgbaPixel {
+    *palette
+        .iter()
+        .min_by_key(|color| {
+            channels(color)
+                .iter()
+                .zip(target)
+                .map(|(&value, target)| (value as i32 - target).pow(2))
+                .sum::<i32>()
+        })
+        .unwrap()
+}
+
+/// Maps every pixel of an image of `width` pixels per row to the closest colour of a palette.
+/// With `dither`, the error of each pixel's colour channels is spread over its neighbours with Floyd–Steinberg error diffusion.
+pub fn remap(
+    pixels: &[RgbaPixel],
+    width: u32,
+    palette: &[RgbaPixel],
+    dither: bool,
+) -> Vec<RgbaPixel> {
+    if palette.is_empty() {
+        return pixels.to_vec();
+    }
+
+    let width = width as usize;
+    if !dither || width == 0 {
+        return pixels
+            .iter()
+            .map(|pixel| nearest(palette, channels(pixel).map(|value| value as i32)))
+            .collect();
+    }
+
+    // the error carried over to the current and next rows, in sixteenths, with a column of padding on both sides
+    let mut current = Vec::new();
+    current.resize(width + 2, [0i32; 3]);
+    let mut next = current.clone();
+    let mut out = Vec::with_capacity(pixels.len());
+
+    for row in pixels.chunks(width) {
+        for (x, pixel) in row.iter().enumerate() {
+            let error = current[x + 1];
+            let values = channels(pixel);
+            let target = [
+                (values[0] as i32 + error[0] / 16).clamp(0, 255),
+                (values[1] as i32 + error[1] / 16).clamp(0, 255),
+                (values[2] as i32 + error[2] / 16).clamp(0, 255),
+                values[3] as i32,
+            ];
+
+            let color = nearest(palette, target);
+            let chosen = channels(&color);
+
+            for channel in 0..3 {
+                let error = target[channel] - chosen[channel] as i32;
+                current[x + 2][channel] += error * 7;
+                next[x][channel] += error * 3;
+                next[x + 1][channel] += error * 5;
+                next[x + 2][channel] += error;
+            }
+
+            out.push(color);
+        }
+
+        core::mem::swap(&mut current, &mut next);
+        next.iter_mut().for_each(|error| *error = [0; 3]);
+    }
+
+    out
+}
+
+/// Quantises an image to at most `colors` colours and encodes it.
+pub fn encode_quantized(
+    header: Header,
+    pixels: &[RgbaPixel],
+    colors: usize,
+    dither: bool,
+) -> Result<Vec<u8>, EncodeError> {
+    quantize(header, pixels, colors, dither).map(|(output, _)| output)
+}
+
+// encode_quantized, also returning the size of the palette that was built
+fn quantize(
+    header: Header,
+    pixels: &[RgbaPixel],
+    colors: usize,
+    dither: bool,
+) -> Result<(Vec<u8>, usize), EncodeError> {
+    let encoder = Encoder::new(header)?;
+    let palette = median_cut(pixels, colors);
+    let output = encoder.pixels_to_vec(&remap(pixels, header.width.get(), &palette, dither))?;
+    Ok((output, palette.len()))
+}
+
+/// Quantises and encodes an image with the largest palette, of up to [MAX_COLORS] colours, whose output fits in `target_size` bytes,
+/// returning the output along with the amount of colours in the palette, which can be fewer than requested for images with few unique colours.
+/// If no palette fits, the image is encoded with a single colour.
+pub fn encode_to_size(
+    header: Header,
+    pixels: &[RgbaPixel],
+    target_size: usize,
+    dither: bool,
+) -> Result<(Vec<u8>, usize), EncodeError> {
+    let mut best = quantize(header, pixels, 1, dither)?;
+
+    // the output mostly grows with the palette, so binary search over its size
+    let (mut low, mut high) = (2, MAX_COLORS);
+    while low <= high {
+        let colors = (low + high) / 2;
+        let output = quantize(header, pixels, colors, dither)?;
+
+        if output.0.len() <= target_size {
+            best = output;
+            low = colors + 1;
+        } else {
+            high = colors - 1;
+        }
+    }
+
+    Ok(best)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::decoder::decode_to_vec;
+    use crate::test_utils::{noisy_image, rng};
+
+    fn gray(v: u8) -> RgbaPixel {
+        RgbaPixel {
+            r: v,
+            g: v,
+            b: v,
+            a: 255,
+        }
+    }
+
+    fn unique(pixels: &[RgbaPixel]) -> Vec<[u8; 4]> {
+        let mut colors: Vec<_> = pixels.iter().map(channels).collect();
+        colors.sort_unstable();
+        colors.dedup();
+        colors
+    }
+
+    #[test]
+    fn median_cut_stops_at_the_unique_colors() {
+        let pixels: Vec<_> = (0..100)
+            .map(|i| gray(if i % 3 == 0 { 10 } else { 200 }))
+            .collect();
+
+        let mut palette = median_cut(&pixels, 256);
+        palette.sort_unstable_by_key(channels);
+        assert_eq!(palette, [gray(10), gray(200)]);
+
+        assert_eq!(median_cut(&pixels, 1), [gray(135)]);
+        assert!(median_cut(&pixels, 0).is_empty());
+        assert!(median_cut(&[], 16).is_empty());
+    }
+
+    #[test]
+    fn median_cut_builds_the_requested_colors() {
+        let (_, pixels) = noisy_image(64, 64);
+        for colors in [1, 2, 7, 64, 256] {
+            let palette = median_cut(&pixels, colors);
+            assert_eq!(palette.len(), colors);
+        }
+    }
+
+    #[test]
+    fn remap_picks_the_nearest_color() {
+        let palette = [gray(0), gray(255)];
+        let pixels = [gray(0), gray(100), gray(127), gray(128), gray(200)];
+        assert_eq!(
+            remap(&pixels, 5, &palette, false),
+            [gray(0), gray(0), gray(0), gray(255), gray(255)]
+        );
+        assert_eq!(remap(&pixels, 5, &[], false), pixels);
+    }
+
+    #[test]
+    fn dithering_keeps_the_average_brightness() {
+        let palette = [gray(0), gray(255)];
+        for v in [32, 128, 200] {
+            let pixels = vec![gray(v); 32 * 32];
+
+            // without dithering every pixel snaps to the same colour
+            let flat = remap(&pixels, 32, &palette, false);
+            assert_eq!(unique(&flat).len(), 1);
+
+            let dithered = remap(&pixels, 32, &palette, true);
+            assert_eq!(dithered.len(), pixels.len());
+            assert!(dithered.iter().all(|pixel| palette.contains(pixel)));
+
+            let mean =
+                dithered.iter().map(|pixel| pixel.r as f64).sum::<f64>() / pixels.len() as f64;
+            assert!((mean - v as f64).abs() < 4.0, "{} dithered to {}", v, mean);
+        }
+    }
+
+    #[test]
+    fn encode_to_size_reports_the_palette_size() {
+        let header = Header::rgba(10, 10);
+        let pixels: Vec<_> = (0..100)
+            .map(|i| gray(if i % 3 == 0 { 10 } else { 200 }))
+            .collect();
+
+        let (output, colors) = encode_to_size(header, &pixels, usize::MAX, false).unwrap();
+        assert_eq!(colors, 2);
+        assert_eq!(decode_to_vec(&output).unwrap().1, pixels);
+    }
+
+    #[test]
+    fn encode_to_size_fits_the_target() {
+        let header = Header::rgba(48, 48);
+        let mut rnd = rng(42);
+        let pixels: Vec<_> = (0..header.pixel_count())
+            .map(|_| gray(rnd() as u8))
+            .collect();
+
+        for target in [2_000, 4_000, 6_000] {
+            let (output, colors) = encode_to_size(header, &pixels, target, true).unwrap();
+            assert!(output.len() <= target, "{} > {}", output.len(), target);
+            assert!(unique(&decode_to_vec(&output).unwrap().1).len() <= colors);
+
+            // one more colour no longer fits
+            if colors < MAX_COLORS {
+                assert!(
+                    encode_quantized(header, &pixels, colors + 1, true)
+                        .unwrap()
+                        .len()
+                        > target
+                );
+            }
+        }
+
+        // with no palette fitting, a single colour is used
+        let (output, colors) = encode_to_size(header, &pixels, 0, false).unwrap();
+        assert_eq!(colors, 1);
+        assert_eq!(unique(&decode_to_vec(&output).unwrap().1).len(), 1);
+    }
+}
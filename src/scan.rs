@@ -0,0 +1,311 @@
+//! Alternative scan orders, feeding pixels to the encoder in an order that may predict better than rows.
+//!
+//! QOI only predicts a pixel from the one before it, so images with vertical structure or small details can shrink when scanned differently.
+//! A scanned file is made of:
+//! - the `qoio` magic
+//! - the [ScanOrder], as a kind byte and a parameter byte
+//! - a standard QOI file, holding the image's pixels in scan order under the image's own header.
+
+use crate::decoder::decode_to_vec;
+use crate::encoder::{check_pixel_count, EncodeError, Encoder};
+use crate::*;
+
+/// Magic bytes for scanned QOI files.
+pub const SCANNED_MAGIC: [u8; 4] = [b'q', b'o', b'i', b'o'];
+
+/// The order pixels are scanned in.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ScanOrder {
+    /// Left to right, top to bottom, like a standard QOI file.
+    Rows,
+    /// Rows alternating between left to right and right to left.
+    Serpentine,
+    /// Top to bottom, left to right.
+    ColumnMajor,
+    /// Square tiles of `1 << tile_bits` pixels per side, in row order, each scanned along a Hilbert curve.
+    /// `tile_bits` must be between 1 and 8.
+    Hilbert { tile_bits: u8 },
+}
+
+/// The orders tried by [encode_smallest].
+pub const CANDIDATES: [ScanOrder; 6] = [
+    ScanOrder::Rows,
+    ScanOrder::Serpentine,
+    ScanOrder::ColumnMajor,
+    ScanOrder::Hilbert { tile_bits: 2 },
+    ScanOrder::Hilbert { tile_bits: 4 },
+    ScanOrder::Hilbert { tile_bits: 6 },
+];
+
+impl ScanOrder {
+    /// The order's kind & parameter bytes.
+    pub fn to_bytes(self) -> [u8; 2] {
+        match self {
+            ScanOrder::Rows => [0, 0],
+            ScanOrder::Serpentine => [1, 0],
+            ScanOrder::ColumnMajor => [2, 0],
+            ScanOrder::Hilbert { tile_bits } => [3, tile_bits],
+        }
+    }
+
+    /// Reads an order from its kind & parameter bytes, returning None if it's unknown or invalid.
+    pub fn from_bytes(bytes: [u8; 2]) -> Option<ScanOrder> {
+        let order = match bytes {
+            [0, 0] => ScanOrder::Rows,
+            [1, 0] => ScanOrder::Serpentine,
+            [2, 0] => ScanOrder::ColumnMajor,
+            [3, tile_bits] => ScanOrder::Hilbert { tile_bits },
+            _ => return None,
+        };
+
+        order.is_valid().then_some(order)
+    }
+
+    fn is_valid(self) -> bool {
+        match self {
+            ScanOrder::Hilbert { tile_bits } => (1..=8).contains(&tile_bits),
+            _ => true,
+        }
+    }
+
+    /// Calls `f` with the row-major index of every pixel of a width x height image, in scan order.
+    pub fn for_each_index(self, width: u32, height: u32, mut f: impl FnMut(usize)) {
+        let (width, height) = (width as usize, height as usize);
+
+        match self {
+            ScanOrder::Rows => (0..width * height).for_each(f),
+            ScanOrder::Serpentine => {
+                for y in 0..height {
+                    let row = y * width;
+                    if y % 2 == 0 {
+                        (row..row + width).for_each(&mut f);
+                    } else {
+                        (row..row + width).rev().for_each(&mut f);
+                    }
+                }
+            }
+            ScanOrder::ColumnMajor => {
+                for x in 0..width {
+                    for y in 0..height {
+                        f(y * width + x);
+                    }
+                }
+            }
+            ScanOrder::Hilbert { tile_bits } => {
+                let side = 1usize << tile_bits;
+                for tile_y in (0..height).step_by(side) {
+                    for tile_x in (0..width).step_by(side) {
+                        // tiles cut off by the image's edges skip the points of the curve outside of it
+                        for d in 0..side * side {
+                            let (x, y) = hilbert_point(side, d);
+                            let (x, y) = (tile_x + x, tile_y + y);
+                            if x < width && y < height {
+                                f(y * width + x);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// maps a distance along the Hilbert curve filling a side x side square to its coordinates
+fn hilbert_point(side: usize, d: usize) -> (usize, usize) {
+    let (mut x, mut y) = (0, 0);
+    let mut t = d;
+    let mut s = 1;
+
+    while s < side {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            core::mem::swap(&mut x, &mut y);
+        }
+
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+
+    (x, y)
+}
+
+/// Encodes an image as a scanned QOI file, feeding its pixels to the encoder in the given order.
+pub fn encode_scanned(
+    header: Header,
+    pixels: &[RgbaPixel],
+    order: ScanOrder,
+) -> Result<Vec<u8>, EncodeError> {
+    if !order.is_valid() {
+        return Err(EncodeError::InvalidHeader);
+    }
+
+    let encoder = Encoder::new(header)?;
+    check_pixel_count(&header, pixels.len())?;
+
+    let mut scanned = Vec::with_capacity(pixels.len());
+    order.for_each_index(header.width.get(), header.height.get(), |i| {
+        scanned.push(pixels[i])
+    });
+
+    let qoi = encoder.pixels_to_vec(&scanned)?;
+
+    let mut out = Vec::with_capacity(6 + qoi.len());
+    out.extend_from_slice(&SCANNED_MAGIC);
+    out.extend_from_slice(&order.to_bytes());
+    out.extend_from_slice(&qoi);
+
+    Ok(out)
+}
+
+/// Encodes an image in every order of [CANDIDATES], keeping the smallest output along with its order.
+pub fn encode_smallest(
+    header: Header,
+    pixels: &[RgbaPixel],
+) -> Result<(Vec<u8>, ScanOrder), EncodeError> {
+    let mut smallest: Option<(Vec<u8>, ScanOrder)> = None;
+
+    for order in CANDIDATES {
+        let out = encode_scanned(header, pixels, order)?;
+        if smallest
+            .as_ref()
+            .is_none_or(|(smallest, _)| out.len() < smallest.len())
+        {
+            smallest = Some((out, order));
+        }
+    }
+
+    Ok(smallest.unwrap())
+}
+
+/// Decodes a scanned QOI file back into row order, returning its header, pixels and scan order. Returns None if the file is invalid.
+pub fn decode_scanned(data: &[u8]) -> Option<(Header, Vec<RgbaPixel>, ScanOrder)> {
+    if *data.get(0..4)? != SCANNED_MAGIC {
+        return None;
+    }
+
+    let order = ScanOrder::from_bytes([*data.get(4)?, *data.get(5)?])?;
+    let (header, scanned) = decode_to_vec(&data[6..])?;
+    if !header.is_valid() {
+        return None;
+    }
+
+    let mut pixels = scanned.clone();
+    let mut scanned = scanned.into_iter();
+    order.for_each_index(header.width.get(), header.height.get(), |i| {
+        if let Some(pixel) = scanned.next() {
+            pixels[i] = pixel;
+        }
+    });
+
+    Some((header, pixels, order))
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::test_utils::{noisy_image, random_pixel, rng};
+
+    fn orders() -> Vec<ScanOrder> {
+        let mut orders = CANDIDATES.to_vec();
+        orders.push(ScanOrder::Hilbert { tile_bits: 1 });
+        orders.push(ScanOrder::Hilbert { tile_bits: 8 });
+        orders
+    }
+
+    const SIZES: [(u32, u32); 6] = [(1, 1), (1, 37), (37, 1), (65, 33), (8, 8), (300, 3)];
+
+    #[test]
+    fn orders_visit_every_pixel_once() {
+        for order in orders() {
+            for (width, height) in SIZES {
+                let mut seen = vec![false; (width * height) as usize];
+                order.for_each_index(width, height, |i| {
+                    assert!(
+                        !seen[i],
+                        "{:?} {}x{} visits {} twice",
+                        order, width, height, i
+                    );
+                    seen[i] = true;
+                });
+                assert!(
+                    seen.iter().all(|&seen| seen),
+                    "{:?} {}x{}",
+                    order,
+                    width,
+                    height
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn scanned_round_trip() {
+        for (width, height) in SIZES {
+            let (header, pixels) = noisy_image(width, height);
+            for order in orders() {
+                let encoded = encode_scanned(header, &pixels, order).unwrap();
+                let (decoded_header, decoded, decoded_order) = decode_scanned(&encoded).unwrap();
+                assert_eq!(decoded_header.as_bytes(), header.as_bytes());
+                assert_eq!(decoded_order, order);
+                assert!(decoded == pixels, "{:?} {}x{}", order, width, height);
+            }
+        }
+    }
+
+    #[test]
+    fn encode_smallest_keeps_the_smallest_candidate() {
+        // random colors per column, so scanning down columns turns the image into runs
+        let (width, height) = (40, 30);
+        let mut rnd = rng(3);
+        let columns: Vec<_> = (0..width).map(|_| random_pixel(&mut rnd)).collect();
+        let pixels: Vec<_> = (0..width * height)
+            .map(|i| columns[(i % width) as usize])
+            .collect();
+
+        let (noisy_header, noisy) = noisy_image(65, 33);
+        let header = Header::rgba(width, height);
+        for (header, pixels) in [(header, pixels.as_slice()), (noisy_header, &noisy)] {
+            let (smallest, order) = encode_smallest(header, pixels).unwrap();
+            assert_eq!(smallest, encode_scanned(header, pixels, order).unwrap());
+            for candidate in CANDIDATES {
+                assert!(smallest.len() <= encode_scanned(header, pixels, candidate).unwrap().len());
+            }
+            assert!(decode_scanned(&smallest).unwrap().1 == pixels);
+        }
+
+        assert_eq!(
+            encode_smallest(header, &pixels).unwrap().1,
+            ScanOrder::ColumnMajor
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_orders() {
+        let (header, pixels) = noisy_image(4, 4);
+        for tile_bits in [0, 9] {
+            let order = ScanOrder::Hilbert { tile_bits };
+            assert!(matches!(
+                encode_scanned(header, &pixels, order),
+                Err(EncodeError::InvalidHeader)
+            ));
+            assert_eq!(ScanOrder::from_bytes(order.to_bytes()), None);
+        }
+        assert_eq!(ScanOrder::from_bytes([0, 1]), None);
+        assert_eq!(ScanOrder::from_bytes([4, 0]), None);
+
+        let mut encoded = encode_scanned(header, &pixels, ScanOrder::Rows).unwrap();
+        assert!(decode_scanned(&encoded[..5]).is_none());
+        encoded[5] = 1;
+        assert!(decode_scanned(&encoded).is_none());
+        encoded[0] = b'x';
+        assert!(decode_scanned(&encoded).is_none());
+    }
+}
@@ -0,0 +1,171 @@
+//! Seek indexes, giving random access to the rows of a QOI file.
+//!
+//! An index records a [Checkpoint] every few rows: the position of the next chunk in the file, along with the decoder's state at that point.
+//! It is stored separately from the QOI file, as a sidecar blob made of:
+//! - the `qoix` magic
+//! - the amount of rows between checkpoints and the amount of checkpoints, as big endian u32s
+//! - every checkpoint.
+
+use crate::decoder::{DecoderState, ImageDecoder, SliceReader};
+use crate::*;
+use zerocopy::{LayoutVerified, U64};
+
+/// Magic bytes for seek indexes.
+pub const INDEX_MAGIC: [u8; 4] = [b'q', b'o', b'i', b'x'];
+
+/// The decoder's state at the start of a row.
+#[derive(AsBytes, FromBytes, Clone, Copy, Debug)]
+#[repr(C)]
+pub struct Checkpoint {
+    pub row: U32<BigEndian>,
+    /// The position of the next chunk in the QOI file.
+    pub offset: U64<BigEndian>,
+    pub state: DecoderState,
+}
+
+/// A seek index over a QOI file.
+#[derive(Clone, Debug)]
+pub struct SeekIndex {
+    /// The amount of rows between checkpoints.
+    pub interval: u32,
+    pub checkpoints: Vec<Checkpoint>,
+}
+
+impl SeekIndex {
+    /// Builds an index by decoding a QOI file, recording a checkpoint every `interval` rows. Returns None if the file is invalid or truncated.
+    pub fn build(data: &[u8], interval: u32) -> Option<SeekIndex> {
+        let (header, reader) = SliceReader::start(data)?;
+        if interval == 0 {
+            return None;
+        }
+
+        let (width, height) = (header.width.get(), header.height.get());
+        let mut decoder = reader.into_decoder();
+        let mut checkpoints = Vec::with_capacity((height / interval + 1) as usize);
+
+        for row in 0..height {
+            if row % interval == 0 {
+                checkpoints.push(decoder.checkpoint(row));
+            }
+
+            for _ in 0..width {
+                decoder.next()?;
+            }
+        }
+
+        Some(SeekIndex {
+            interval,
+            checkpoints,
+        })
+    }
+
+    /// Serializes the index into a sidecar blob.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out =
+            Vec::with_capacity(12 + self.checkpoints.len() * core::mem::size_of::<Checkpoint>());
+        out.extend_from_slice(&INDEX_MAGIC);
+        out.extend_from_slice(U32::<BigEndian>::new(self.interval).as_bytes());
+        out.extend_from_slice(U32::<BigEndian>::new(self.checkpoints.len() as u32).as_bytes());
+        out.extend_from_slice(self.checkpoints.as_bytes());
+        out
+    }
+
+    /// Reads an index from a sidecar blob. Returns None if it is invalid.
+    pub fn from_bytes(data: &[u8]) -> Option<SeekIndex> {
+        if *data.get(0..4)? != INDEX_MAGIC {
+            return None;
+        }
+
+        let interval = U32::<BigEndian>::read_from(data.get(4..8)?)?.get();
+        if interval == 0 {
+            return None;
+        }
+
+        let count = U32::<BigEndian>::read_from(data.get(8..12)?)?.get() as usize;
+        let end = 12usize.checked_add(count.checked_mul(core::mem::size_of::<Checkpoint>())?)?;
+        let checkpoints = LayoutVerified::<_, [Checkpoint]>::new_slice(data.get(12..end)?)?;
+
+        Some(SeekIndex {
+            interval,
+            checkpoints: checkpoints.to_vec(),
+        })
+    }
+
+    /// Finds the last checkpoint at or before a row.
+    pub fn checkpoint_for_row(&self, row: u32) -> Option<&Checkpoint> {
+        self.checkpoints.get((row / self.interval) as usize)
+    }
+
+    /// Builds a decoder for `data` starting at `row`, decoding from the nearest checkpoint and skipping the rows in between.
+    /// Returns None if the row is out of bounds, or the file doesn't match the index.
+    pub fn decoder_at_row<'a>(
+        &self,
+        data: &'a [u8],
+        row: u32,
+    ) -> Option<ImageDecoder<SliceReader<'a>>> {
+        let (header, _) = SliceReader::start(data)?;
+        if row >= header.height.get() {
+            return None;
+        }
+
+        let checkpoint = self.checkpoint_for_row(row)?;
+        let mut decoder = ImageDecoder::resume(data, checkpoint)?;
+
+        let skipped =
+            (row.checked_sub(checkpoint.row.get())?) as usize * header.width.get() as usize;
+        for _ in 0..skipped {
+            decoder.next()?;
+        }
+
+        Some(decoder)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::encoder::Encoder;
+    use crate::test_utils::noisy_image;
+
+    #[test]
+    fn decoder_at_row_matches_full_decode() {
+        let (header, pixels) = noisy_image(31, 50);
+        let file = Encoder::new(header)
+            .unwrap()
+            .pixels_to_vec(&pixels)
+            .unwrap();
+
+        for interval in [1, 4, 7, 50, 64] {
+            let index = SeekIndex::build(&file, interval).unwrap();
+            let index = SeekIndex::from_bytes(&index.to_bytes()).unwrap();
+            assert_eq!(index.interval, interval);
+            assert_eq!(index.checkpoints.len(), 50usize.div_ceil(interval as usize));
+
+            for (row, expected) in pixels.chunks(31).enumerate() {
+                let decoder = index.decoder_at_row(&file, row as u32).unwrap();
+                let decoded: Vec<RgbaPixel> = decoder.take(31).collect();
+                assert!(decoded == expected, "interval {}, row {}", interval, row);
+            }
+
+            assert!(index.decoder_at_row(&file, 50).is_none());
+            assert!(index.decoder_at_row(&file, u32::MAX).is_none());
+        }
+    }
+
+    #[test]
+    fn from_bytes_rejects_invalid_blobs() {
+        let (header, pixels) = noisy_image(31, 50);
+        let file = Encoder::new(header)
+            .unwrap()
+            .pixels_to_vec(&pixels)
+            .unwrap();
+        let blob = SeekIndex::build(&file, 8).unwrap().to_bytes();
+
+        assert!(SeekIndex::from_bytes(&blob[..blob.len() - 1]).is_none());
+        assert!(SeekIndex::from_bytes(&blob[1..]).is_none());
+
+        let mut no_interval = blob;
+        no_interval[4..8].copy_from_slice(&[0; 4]);
+        assert!(SeekIndex::from_bytes(&no_interval).is_none());
+    }
+}
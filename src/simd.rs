@@ -0,0 +1,218 @@
+//! Block analysis for the encoder: for several pixels at once, find which repeat the previous pixel, their index positions and their differences from the previous pixel.
+
+use crate::RgbaPixel;
+
+/// The amount of pixels analyzed at once.
+pub(crate) const LANES: usize = 8;
+
+/// The analysis of a block of pixels.
+#[derive(Default)]
+pub(crate) struct Analysis {
+    /// Bit i is set if pixel i is the same as the pixel before it.
+    pub same: u8,
+    /// The index position of each pixel.
+    pub hash: [u8; LANES],
+    /// The per-channel wrapping difference between each pixel and the pixel before it.
+    pub diff: [[u8; 4]; LANES],
+}
+
+/// Analyzes a window made of the pixel preceding a block, followed by the block itself.
+pub(crate) type Analyzer = unsafe fn(&[RgbaPixel; LANES + 1], &mut Analysis);
+
+/// Picks the fastest analyzer supported by the current CPU.
+#[allow(unreachable_code)]
+pub(crate) fn analyzer() -> Analyzer {
+    #[cfg(all(feature = "std", any(target_arch = "x86", target_arch = "x86_64")))]
+    {
+        if std::is_x86_feature_detected!("avx2") {
+            return x86::analyze_avx2;
+        }
+
+        if std::is_x86_feature_detected!("sse2") {
+            return x86::analyze_sse2;
+        }
+    }
+
+    #[cfg(all(
+        not(feature = "std"),
+        any(target_arch = "x86", target_arch = "x86_64"),
+        target_feature = "avx2"
+    ))]
+    return x86::analyze_avx2;
+
+    #[cfg(all(
+        not(feature = "std"),
+        any(target_arch = "x86", target_arch = "x86_64"),
+        target_feature = "sse2"
+    ))]
+    return x86::analyze_sse2;
+
+    #[cfg(all(feature = "std", target_arch = "aarch64"))]
+    if std::arch::is_aarch64_feature_detected!("neon") {
+        return neon::analyze_neon;
+    }
+
+    #[cfg(all(not(feature = "std"), target_arch = "aarch64", target_feature = "neon"))]
+    return neon::analyze_neon;
+
+    analyze_scalar
+}
+
+/// Every analyzer supported by the current CPU, so that tests can check them against each other.
+#[cfg(all(test, feature = "std"))]
+pub(crate) fn supported_analyzers() -> Vec<(&'static str, Analyzer)> {
+    let mut analyzers: Vec<(&'static str, Analyzer)> = vec![("scalar", analyze_scalar)];
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if std::is_x86_feature_detected!("sse2") {
+            analyzers.push(("sse2", x86::analyze_sse2));
+        }
+
+        if std::is_x86_feature_detected!("avx2") {
+            analyzers.push(("avx2", x86::analyze_avx2));
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    if std::arch::is_aarch64_feature_detected!("neon") {
+        analyzers.push(("neon", neon::analyze_neon));
+    }
+
+    analyzers
+}
+
+fn analyze_scalar(window: &[RgbaPixel; LANES + 1], out: &mut Analysis) {
+    out.same = 0;
+
+    for i in 0..LANES {
+        let (previous, pixel) = (window[i], window[i + 1]);
+
+        out.same |= ((pixel == previous) as u8) << i;
+        out.hash[i] = pixel.index_position();
+        out.diff[i] = [
+            pixel.r.wrapping_sub(previous.r),
+            pixel.g.wrapping_sub(previous.g),
+            pixel.b.wrapping_sub(previous.b),
+            pixel.a.wrapping_sub(previous.a),
+        ];
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod x86 {
+    use super::{Analysis, LANES};
+    use crate::RgbaPixel;
+
+    #[cfg(target_arch = "x86")]
+    use core::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use core::arch::x86_64::*;
+
+    // the index position weights, for two pixels of 16-bit channels
+    const WEIGHTS: [i16; 8] = [3, 5, 7, 11, 3, 5, 7, 11];
+
+    #[target_feature(enable = "sse2")]
+    pub(super) unsafe fn analyze_sse2(window: &[RgbaPixel; LANES + 1], out: &mut Analysis) {
+        let ptr = window.as_ptr() as *const u8;
+        let zero = _mm_setzero_si128();
+        let weights = _mm_loadu_si128(WEIGHTS.as_ptr() as *const __m128i);
+        let mut same = 0;
+
+        // four pixels at a time
+        for half in 0..2 {
+            let previous = _mm_loadu_si128(ptr.add(half * 16) as *const __m128i);
+            let pixels = _mm_loadu_si128(ptr.add(half * 16 + 4) as *const __m128i);
+
+            let equal = _mm_cmpeq_epi32(pixels, previous);
+            same |= (_mm_movemask_ps(_mm_castsi128_ps(equal)) as u8) << (half * 4);
+
+            let diff = _mm_sub_epi8(pixels, previous);
+            _mm_storeu_si128(out.diff.as_mut_ptr().add(half * 4) as *mut __m128i, diff);
+
+            // widen channels to 16 bits, multiply by their weights & sum them up per pixel
+            let low = _mm_madd_epi16(_mm_unpacklo_epi8(pixels, zero), weights);
+            let high = _mm_madd_epi16(_mm_unpackhi_epi8(pixels, zero), weights);
+            let sums = _mm_madd_epi16(_mm_packs_epi32(low, high), _mm_set1_epi16(1));
+            let hashes = _mm_and_si128(sums, _mm_set1_epi32(63));
+
+            let mut lanes = [0u32; 4];
+            _mm_storeu_si128(lanes.as_mut_ptr() as *mut __m128i, hashes);
+            for (i, hash) in lanes.iter().enumerate() {
+                out.hash[half * 4 + i] = *hash as u8;
+            }
+        }
+
+        out.same = same;
+    }
+
+    #[cfg(any(feature = "std", target_feature = "avx2"))]
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn analyze_avx2(window: &[RgbaPixel; LANES + 1], out: &mut Analysis) {
+        let ptr = window.as_ptr() as *const u8;
+        let zero = _mm256_setzero_si256();
+        let weights =
+            _mm256_broadcastsi128_si256(_mm_loadu_si128(WEIGHTS.as_ptr() as *const __m128i));
+
+        let previous = _mm256_loadu_si256(ptr as *const __m256i);
+        let pixels = _mm256_loadu_si256(ptr.add(4) as *const __m256i);
+
+        let equal = _mm256_cmpeq_epi32(pixels, previous);
+        out.same = _mm256_movemask_ps(_mm256_castsi256_ps(equal)) as u8;
+
+        let diff = _mm256_sub_epi8(pixels, previous);
+        _mm256_storeu_si256(out.diff.as_mut_ptr() as *mut __m256i, diff);
+
+        // unpacking works within 128-bit lanes, so low holds pixels 0, 1, 4 & 5 and high holds pixels 2, 3, 6 & 7.
+        // packing them back together puts the pixels back in order.
+        let low = _mm256_madd_epi16(_mm256_unpacklo_epi8(pixels, zero), weights);
+        let high = _mm256_madd_epi16(_mm256_unpackhi_epi8(pixels, zero), weights);
+        let sums = _mm256_madd_epi16(_mm256_packs_epi32(low, high), _mm256_set1_epi16(1));
+        let hashes = _mm256_and_si256(sums, _mm256_set1_epi32(63));
+
+        let mut lanes = [0u32; LANES];
+        _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, hashes);
+        for (i, hash) in lanes.iter().enumerate() {
+            out.hash[i] = *hash as u8;
+        }
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use super::{Analysis, LANES};
+    use crate::RgbaPixel;
+    use core::arch::aarch64::*;
+
+    const BITS: [u8; LANES] = [1, 2, 4, 8, 16, 32, 64, 128];
+
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn analyze_neon(window: &[RgbaPixel; LANES + 1], out: &mut Analysis) {
+        let ptr = window.as_ptr() as *const u8;
+
+        // deinterleave into one vector per channel
+        let previous = vld4_u8(ptr);
+        let pixels = vld4_u8(ptr.add(4));
+
+        let diff = uint8x8x4_t(
+            vsub_u8(pixels.0, previous.0),
+            vsub_u8(pixels.1, previous.1),
+            vsub_u8(pixels.2, previous.2),
+            vsub_u8(pixels.3, previous.3),
+        );
+        vst4_u8(out.diff.as_mut_ptr() as *mut u8, diff);
+
+        let equal = vand_u8(
+            vand_u8(vceq_u8(pixels.0, previous.0), vceq_u8(pixels.1, previous.1)),
+            vand_u8(vceq_u8(pixels.2, previous.2), vceq_u8(pixels.3, previous.3)),
+        );
+        out.same = vaddv_u8(vand_u8(equal, vld1_u8(BITS.as_ptr())));
+
+        // wrapping 8-bit arithmetic is fine, as 64 divides 256
+        let mut hashes = vmul_u8(pixels.0, vdup_n_u8(3));
+        hashes = vmla_u8(hashes, pixels.1, vdup_n_u8(5));
+        hashes = vmla_u8(hashes, pixels.2, vdup_n_u8(7));
+        hashes = vmla_u8(hashes, pixels.3, vdup_n_u8(11));
+        vst1_u8(out.hash.as_mut_ptr(), vand_u8(hashes, vdup_n_u8(63)));
+    }
+}
@@ -0,0 +1,416 @@
+//! Striped QOI images, split into horizontal stripes that are encoded and decoded independently - in parallel, with the rayon feature.
+//!
+//! A striped file is made of:
+//! - the `qois` magic
+//! - a [StripedHeader]
+//! - a table holding the end offset of every stripe, as big endian u64s counted from the end of the table
+//! - every stripe, as a standard QOI file.
+//!
+//! [encode_stitched] also encodes stripes in parallel, but stitches them back into a single standard QOI stream.
+
+use crate::decoder::SliceReader;
+use crate::encoder::{check_pixel_count, EncodeError, Encoder};
+use crate::*;
+use zerocopy::{LayoutVerified, U64};
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+/// Magic bytes for striped QOI files.
+pub const STRIPED_MAGIC: [u8; 4] = [b'q', b'o', b'i', b's'];
+
+/// The header of a striped QOI file.
+#[derive(AsBytes, FromBytes, Clone, Copy, Debug)]
+#[repr(C)]
+pub struct StripedHeader {
+    /// The header of the whole image.
+    pub header: Header,
+    /// The amount of rows in each stripe. The last stripe may be shorter.
+    pub stripe_height: U32<BigEndian>,
+    pub stripe_count: U32<BigEndian>,
+}
+
+// runs f over the stripes of an image, in parallel if possible
+#[cfg(feature = "rayon")]
+fn map_stripes<T, R, F>(pixels: &[T], stripe_len: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(usize, &[T]) -> R + Sync + Send,
+{
+    pixels
+        .par_chunks(stripe_len)
+        .enumerate()
+        .map(|(i, stripe)| f(i, stripe))
+        .collect()
+}
+
+#[cfg(not(feature = "rayon"))]
+fn map_stripes<T, R, F>(pixels: &[T], stripe_len: usize, f: F) -> Vec<R>
+where
+    F: Fn(usize, &[T]) -> R,
+{
+    pixels
+        .chunks(stripe_len)
+        .enumerate()
+        .map(|(i, stripe)| f(i, stripe))
+        .collect()
+}
+
+/// Encodes an image as a striped QOI file, made of independent stripes of `stripe_height` rows.
+pub fn encode_striped(
+    header: Header,
+    pixels: &[RgbaPixel],
+    stripe_height: u32,
+) -> Result<Vec<u8>, EncodeError> {
+    if !header.is_valid() || stripe_height == 0 {
+        return Err(EncodeError::InvalidHeader);
+    }
+
+    check_pixel_count(&header, pixels.len())?;
+
+    let width = header.width.get();
+    let stripe_len = width as usize * stripe_height as usize;
+
+    let stripes = map_stripes(pixels, stripe_len, |_, stripe| {
+        let stripe_header = Header {
+            height: (stripe.len() as u32 / width).into(),
+            ..header
+        };
+
+        Encoder::new(stripe_header)?.pixels_to_vec(stripe)
+    })
+    .into_iter()
+    .collect::<Result<Vec<_>, _>>()?;
+
+    let striped_header = StripedHeader {
+        header,
+        stripe_height: stripe_height.into(),
+        stripe_count: (stripes.len() as u32).into(),
+    };
+
+    let table_len = stripes.len() * 8;
+    let data_len: usize = stripes.iter().map(Vec::len).sum();
+    let mut out = Vec::with_capacity(4 + 18 + table_len + data_len);
+
+    out.extend_from_slice(&STRIPED_MAGIC);
+    out.extend_from_slice(striped_header.as_bytes());
+
+    let mut end = 0u64;
+    for stripe in &stripes {
+        end += stripe.len() as u64;
+        out.extend_from_slice(U64::<BigEndian>::new(end).as_bytes());
+    }
+
+    for stripe in &stripes {
+        out.extend_from_slice(stripe);
+    }
+
+    Ok(out)
+}
+
+/// Splits a striped QOI file into its header and its stripes, each a standard QOI file. Returns None if the file is invalid.
+pub fn read_stripes(data: &[u8]) -> Option<(StripedHeader, Vec<&[u8]>)> {
+    if *data.get(0..4)? != STRIPED_MAGIC {
+        return None;
+    }
+
+    let header = StripedHeader::read_from(data.get(4..22)?)?;
+    let count = header.stripe_count.get() as usize;
+    let table_end = 22usize.checked_add(count.checked_mul(8)?)?;
+    let table = LayoutVerified::<_, [U64<BigEndian>]>::new_slice(data.get(22..table_end)?)?;
+    let body = &data[table_end..];
+
+    let mut stripes = Vec::with_capacity(count);
+    let mut start = 0;
+    for end in table.iter() {
+        let end = usize::try_from(end.get()).ok()?;
+        stripes.push(body.get(start..end)?);
+        start = end;
+    }
+
+    Some((header, stripes))
+}
+
+/// Decodes a striped QOI file into a Vec of pixels, decoding stripes in parallel with the rayon feature. Returns None if the file is invalid.
+pub fn decode_striped(data: &[u8]) -> Option<(Header, Vec<RgbaPixel>)> {
+    let (striped_header, stripes) = read_stripes(data)?;
+    let header = striped_header.header;
+    let (width, height) = (header.width.get(), header.height.get());
+    let stripe_height = striped_header.stripe_height.get();
+
+    if !header.is_valid() || stripe_height == 0 {
+        return None;
+    }
+
+    let stripe_count = height / stripe_height + (height % stripe_height != 0) as u32;
+    if stripes.len() != stripe_count as usize {
+        return None;
+    }
+
+    // each stripe is a QOI file with a 14 byte header, the rest of it holds at most 62 pixels per byte
+    let chunk_bytes = stripes.iter().map(|s| s.len().saturating_sub(14)).sum();
+    let mut pixels = crate::decoder::pixel_buffer(header.pixel_count() as u64, chunk_bytes)?;
+    pixels.resize(
+        width as usize * height as usize,
+        RgbaPixel {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 0,
+        },
+    );
+
+    let stripe_len = width as usize * stripe_height as usize;
+    let decode_stripe = |(out, data): (&mut [RgbaPixel], &&[u8])| -> Option<()> {
+        let (stripe_header, reader) = SliceReader::start(data)?;
+        if stripe_header.width.get() != width
+            || stripe_header.height.get() as usize * width as usize != out.len()
+        {
+            return None;
+        }
+
+        reader.decode_into(out)
+    };
+
+    #[cfg(feature = "rayon")]
+    let decoded = pixels
+        .par_chunks_mut(stripe_len)
+        .zip(stripes.par_iter())
+        .map(decode_stripe)
+        .collect::<Option<()>>();
+
+    #[cfg(not(feature = "rayon"))]
+    let decoded = pixels
+        .chunks_mut(stripe_len)
+        .zip(stripes.iter())
+        .map(decode_stripe)
+        .collect::<Option<()>>();
+
+    decoded?;
+
+    Some((header, pixels))
+}
+
+// for each index slot, the position of the first pixel of a stripe to look it up, past the stripe's first pixel.
+// pixels that differ from the one before them look up their slot, whatever state the encoder is in
+fn first_lookups(stripe: &[RgbaPixel]) -> [Option<usize>; 64] {
+    let mut lookups = [None; 64];
+    let mut found = 0;
+
+    for (position, pair) in stripe.windows(2).enumerate() {
+        if pair[0] != pair[1] {
+            let slot = &mut lookups[pair[1].index_position() as usize];
+            if slot.is_none() {
+                *slot = Some(position + 1);
+                found += 1;
+                if found == 64 {
+                    break;
+                }
+            }
+        }
+    }
+
+    lookups
+}
+
+/// Encodes an image as a single standard QOI stream, encoding stripes of `stripe_height` rows in parallel with the rayon feature.
+///
+/// Every stripe is first encoded independently. Then, each stripe is re-encoded from where the previous one left off,
+/// until that encoder emits the same chunks as the stripe's own encoder would from there on: usually once the first run ends,
+/// though index slots still holding pixels from earlier stripes may stretch this out to where they're next looked up.
+/// The rest of the stripe is then copied over. The output is the same as encoding the image in one go.
+pub fn encode_stitched(
+    header: Header,
+    pixels: &[RgbaPixel],
+    stripe_height: u32,
+) -> Result<Vec<u8>, EncodeError> {
+    stitch(header, pixels, stripe_height).map(|(out, _)| out)
+}
+
+// encode_stitched, also returning the amount of pixels encoded serially while stitching
+fn stitch(
+    header: Header,
+    pixels: &[RgbaPixel],
+    stripe_height: u32,
+) -> Result<(Vec<u8>, usize), EncodeError> {
+    if stripe_height == 0 {
+        return Err(EncodeError::InvalidHeader);
+    }
+
+    let encoder = Encoder::new(header)?;
+    check_pixel_count(&header, pixels.len())?;
+
+    let stripe_len = header.width.get() as usize * stripe_height as usize;
+
+    // encode every stripe as if it started a new image, keeping its final state to carry it over to the next stripe
+    let stripes = map_stripes(pixels, stripe_len, |i, stripe| {
+        let mut encoder = encoder.clone();
+        encoder.start_at((i * stripe_len) as u32);
+
+        let mut bytes = Vec::with_capacity(stripe.len() * (header.channels as usize + 1));
+        encoder
+            .process_pixels(stripe, |chunk| chunk.write_to_vec(&mut bytes))
+            .map(|_| (bytes, encoder.snapshot(), first_lookups(stripe)))
+    })
+    .into_iter()
+    .collect::<Result<Vec<_>, _>>()?;
+
+    let mut out = Vec::with_capacity(14 + stripes.iter().map(|(b, ..)| b.len()).sum::<usize>() + 8);
+    out.extend_from_slice(&tags::QOI_MAGIC);
+    out.extend_from_slice(header.as_bytes());
+
+    let mut carried = encoder.clone();
+    let mut serial = 0;
+    for (i, (stripe, (bytes, stripe_end, lookups))) in
+        pixels.chunks(stripe_len).zip(stripes).enumerate()
+    {
+        // replays the stripe's own encoder, to know how many bytes it emitted so far
+        let mut replay = encoder.clone();
+        replay.start_at((i * stripe_len) as u32);
+        let mut replayed_bytes = 0;
+        let mut converged = false;
+
+        for (position, &pixel) in stripe.iter().enumerate() {
+            // past the first pixel, both encoders look up the same slots at the same positions
+            let next_lookup = |slot: usize| lookups[slot].map(|lookup| stripe[lookup]);
+            if (position == 0 && carried.snapshot() == replay.snapshot())
+                || (position > 0 && carried.converges_with(&replay, next_lookup))
+            {
+                converged = true;
+                break;
+            }
+
+            for chunk in carried.process_pixel(pixel)? {
+                chunk.write_to_vec(&mut out);
+            }
+
+            for chunk in replay.process_pixel(pixel)? {
+                replayed_bytes += chunk.encoded_len();
+            }
+
+            serial += 1;
+        }
+
+        if converged {
+            out.extend_from_slice(&bytes[replayed_bytes..]);
+
+            // slots that still differ & aren't looked up again keep their carried pixels; the rest match the stripe's encoder
+            let (carried_state, replay_state) = (carried.snapshot(), replay.snapshot());
+            let mut state = stripe_end;
+            for (slot, lookup) in lookups.iter().enumerate() {
+                if lookup.is_none()
+                    && carried_state.previously_seen[slot] != replay_state.previously_seen[slot]
+                {
+                    state.previously_seen[slot] = carried_state.previously_seen[slot];
+                }
+            }
+
+            carried.restore(state)?;
+        }
+    }
+
+    carried.finish()?;
+    out.extend_from_slice(&tags::BYTESTREAM_END);
+
+    Ok((out, serial))
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::encoder::Encoder;
+    use crate::test_utils::rng;
+
+    fn gray(v: u8) -> RgbaPixel {
+        RgbaPixel {
+            r: v,
+            g: v,
+            b: v,
+            a: 255,
+        }
+    }
+
+    // a few noisy rows filling the whole index, then a flat body that never looks most slots up again
+    fn noisy_top(width: u32, height: u32) -> (Header, Vec<RgbaPixel>) {
+        let mut rnd = rng(0x9e3779b97f4a7c15);
+        let pixels = (0..width * height)
+            .map(|i| {
+                if i < width * 4 {
+                    let [r, g, b, a, ..] = rnd().to_le_bytes();
+                    RgbaPixel { r, g, b, a }
+                } else {
+                    gray(((i / width) / 16) as u8)
+                }
+            })
+            .collect();
+
+        (Header::rgba(width, height), pixels)
+    }
+
+    // a smooth gradient with some noise & a few alpha changes, roughly what a photo with an overlay looks like
+    fn photo_like(width: u32, height: u32) -> (Header, Vec<RgbaPixel>) {
+        let mut rnd = rng(0x2545f4914f6cdd1d);
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let noise = (rnd() % 5) as u8;
+                RgbaPixel {
+                    r: (x / 3) as u8 + noise,
+                    g: (y / 2) as u8,
+                    b: ((x + y) / 5) as u8 + noise / 2,
+                    a: if (x / 40 + y / 40) % 7 == 0 { 200 } else { 255 },
+                }
+            })
+            .collect();
+
+        (Header::rgba(width, height), pixels)
+    }
+
+    #[test]
+    fn stitched_matches_serial() {
+        let mut rnd = rng(0xda942042e4dd58b5);
+        let random = {
+            let pixels = (0..97 * 61)
+                .map(|_| match rnd() % 4 {
+                    0 => gray(0),
+                    1 => gray(255),
+                    _ => gray((rnd() % 8) as u8 * 32),
+                })
+                .collect();
+            (Header::rgba(97, 61), pixels)
+        };
+
+        for (header, pixels) in [noisy_top(64, 48), photo_like(131, 70), random] {
+            let serial = Encoder::new(header)
+                .unwrap()
+                .pixels_to_vec(&pixels)
+                .unwrap();
+            for stripe_height in [1, 2, 3, 7, 16, 100] {
+                let stitched = encode_stitched(header, &pixels, stripe_height).unwrap();
+                assert!(stitched == serial, "stripe height {}", stripe_height);
+            }
+        }
+    }
+
+    #[test]
+    fn stitching_reencodes_a_bounded_window() {
+        for (header, pixels) in [noisy_top(512, 256), photo_like(512, 256)] {
+            let serial = Encoder::new(header)
+                .unwrap()
+                .pixels_to_vec(&pixels)
+                .unwrap();
+            let (stitched, reencoded) = stitch(header, &pixels, 16).unwrap();
+            assert!(stitched == serial);
+
+            // at most a couple of rows per stripe, rather than the whole image
+            let stripes = header.height.get() as usize / 16;
+            assert!(
+                reencoded <= stripes * 2 * header.width.get() as usize,
+                "re-encoded {} of {} pixels",
+                reencoded,
+                pixels.len()
+            );
+        }
+    }
+}
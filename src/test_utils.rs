@@ -0,0 +1,59 @@
+//! Inputs shared by the test modules.
+
+use crate::*;
+
+/// A noisy image with runs & alpha changes, so every chunk type shows up.
+pub(crate) fn noisy_image(width: u32, height: u32) -> (Header, Vec<RgbaPixel>) {
+    let header = Header::rgba(width, height);
+    let pixels = (0..header.pixel_count())
+        .map(|i| {
+            let v = (i / 3).wrapping_mul(2654435761);
+            RgbaPixel {
+                r: (v >> 24) as u8,
+                g: (v >> 24) as u8 ^ (i % 4) as u8,
+                b: (v >> 16) as u8,
+                a: if i % 97 < 40 { 255 } else { 128 },
+            }
+        })
+        .collect();
+
+    (header, pixels)
+}
+
+/// A xorshift generator, so that random inputs are the same on every run.
+pub(crate) fn rng(mut seed: u64) -> impl FnMut() -> u64 {
+    move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed
+    }
+}
+
+pub(crate) fn random_pixel(rnd: &mut impl FnMut() -> u64) -> RgbaPixel {
+    let [r, g, b, a, ..] = rnd().to_le_bytes();
+    RgbaPixel { r, g, b, a }
+}
+
+/// Grayscale bytes made of runs around the 62 pixel limit, with alpha changes for [GrayLayout::La8], ending in a run longer than 62 pixels.
+pub(crate) fn gray_image(layout: GrayLayout) -> Vec<u8> {
+    let mut rnd = rng(0x2545f4914f6cdd1d);
+    let mut bytes = Vec::new();
+    let mut push = |length: usize, l: u8, a: u8| {
+        for _ in 0..length {
+            bytes.push(l);
+            if layout == GrayLayout::La8 {
+                bytes.push(a);
+            }
+        }
+    };
+
+    for _ in 0..200 {
+        let [l, a, length, ..] = rnd().to_le_bytes();
+        let length = [1, 1, 2, 3, 61, 62, 63, 64, 124, 125, 200][length as usize % 11];
+        push(length, l % 8 * 4, if a % 4 == 0 { a } else { 255 });
+    }
+    push(70, 200, 255);
+
+    bytes
+}